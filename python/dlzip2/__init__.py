@@ -1,4 +1,12 @@
-from dlzip2.compression_utils import compress, decompress
+from dlzip2._dlzip2 import (ChecksumError, CorruptDataError, Dlzip2Compressor,
+                            Dlzip2Decompressor, Dlzip2Error,
+                            TruncatedInputError)
+from dlzip2.compression_utils import (compress, decompress, stream_info,
+                                      verify)
+from dlzip2.dlzip2_file import Dlzip2File, open
 from dlzip2.main import dlzip2_cli as _cli
 
-__all__ = ['compress', 'decompress', '_cli']
\ No newline at end of file
+__all__ = ['compress', 'decompress', 'verify', 'stream_info', 'open',
+           'Dlzip2File',
+           'Dlzip2Compressor', 'Dlzip2Decompressor', 'Dlzip2Error', 'CorruptDataError',
+           'TruncatedInputError', 'ChecksumError', '_cli']
@@ -1,17 +1,48 @@
-from dlzip2._dlzip2 import _compress, _decompress
+from dlzip2._dlzip2 import (_compress, _compress_bz2, _decompress,
+                            _stream_info, _verify)
 
 
-def compress(content: list[int] | bytes | str) -> bytes:
+def _byte_view(content) -> memoryview:
+    """Bytes of an object supporting the buffer protocol, without copy"""
+    try:
+        return memoryview(content).cast('B')
+    except TypeError:
+        raise TypeError(
+            f"content should support the buffer protocol and be contiguous, "
+            f"not {type(content)}") from None
+
+
+def compress(content: list[int] | bytes | str | memoryview, *,
+             format: str = 'dlz2', multi_table: bool = False,
+             max_code_len: int = 17, threads: int = 1) -> bytes:
     """Compress content using dlzip2 algorithm
     This function is just a wrapper around the Rust function responsible of
     the compression
 
     Parameters
     -----------
-    content : list[int] | bytes | str
+    content : list[int] | bytes | str | memoryview
         the element to be compressed. It will commpress the utf8
         representation of the element, so in case of list[int], all
-        element should be in [0, 256[
+        element should be in [0, 256[. Any object supporting the buffer
+        protocol (bytearray, memoryview, mmap, numpy uint8 array...) is
+        compressed without being copied
+    format : str = 'dlz2'
+        {'dlz2', 'bz2'} format of the output, 'bz2' produces a genuine bzip2
+        stream that can be read by bunzip2 or the bz2 module
+    multi_table : bool = False
+        for the 'dlz2' format, code each block with several Huffman tables
+        switched every 50 symbols (as bzip2 does). Slower, but gives a
+        better compression ratio, especially on text. The 'bz2' format
+        always uses several tables
+    max_code_len : int = 17
+        for the 'dlz2' format, maximum length of a Huffman code in bits,
+        between 9 and 20. Longer codes are shortened with the package-merge
+        algorithm, at a small cost in compression ratio
+    threads : int = 1
+        for the 'dlz2' format, number of blocks compressed in parallel, each
+        on its own thread (as pbzip2 does), 0 for one per cpu, capped to 4
+        per cpu. The output doesn't depend on it
 
     Returns
     ---------
@@ -21,31 +52,38 @@ def compress(content: list[int] | bytes | str) -> bytes:
 
     if isinstance(content, str):
         content = bytes(content, "utf8")
-        return bytes(_compress(content))
-    elif isinstance(content, bytes):
-        return bytes(_compress(content))
     elif isinstance(content, list):
         if min(content) < 0 or max(content) > 255:
             raise ValueError(
                 "list element should be between 0 and 255 (included),"
                 "for utf8 representation")
-        return bytes(_compress(bytes(content)))
+        content = bytes(content)
+    elif not isinstance(content, bytes):
+        content = _byte_view(content)
+
+    if threads < 0:
+        raise ValueError("threads should be positive, or 0 for one per cpu")
+
+    if format == 'dlz2':
+        return _compress(content, multi_table, max_code_len, threads)
+    elif format == 'bz2':
+        return _compress_bz2(content)
     else:
-        raise TypeError(
-            f"content should be either list[int] | bytes | str, not"
-            f" {type(content)}")
+        raise ValueError("format should be in {'dlz2', 'bz2'}")
 
 
-def decompress(content: bytes, *, return_type='bytes') -> bytes | str:
+def decompress(content: bytes | memoryview, *,
+               return_type='bytes') -> bytes | str:
     """Decompress the bytes of data using the inverse transformation of
-    compress
+    compress, bzip2 streams are recognised and decompressed too
     This function is just a wrapper around the Rust function responsible of
     the decompression
 
     Parameters
     -----------
-    content : bytes
-        bytes of data to decrompress
+    content : bytes | memoryview
+        bytes of data to decrompress, or any object supporting the buffer
+        protocol (bytearray, memoryview, mmap...), read without being copied
     return_type : str = 'bytes'
         {'str', 'bytes'} type of the return element
 
@@ -53,13 +91,83 @@ def decompress(content: bytes, *, return_type='bytes') -> bytes | str:
     ---------
     bytes | str
         decompressed content with type specified by return_type
+
+    Raises
+    ---------
+    Dlzip2Error
+        (a ValueError) if content is neither a dlzip2 nor a bzip2 stream
+        (wrong magic bytes, unsupported format version). Its subclasses
+        are raised when content is a damaged stream :
+        TruncatedInputError if it ends too early, ChecksumError if a block
+        or stream checksum doesn't match the decompressed content,
+        CorruptDataError if any other part is inconsistent
+    ValueError
+        if return_type is invalid
     """
     if not isinstance(content, bytes):
-        raise TypeError(f"content should be bytes, not {type(content)}")
+        content = _byte_view(content)
 
     if return_type == 'bytes':
-        return bytes(_decompress(content))
+        return _decompress(content)
     elif return_type == 'str':
-        return bytes(_decompress(content)).decode()
+        return _decompress(content).decode()
     else:
-        raise ValueError("return_type should be in {'bytes', 'str'}")
\ No newline at end of file
+        raise ValueError("return_type should be in {'bytes', 'str'}")
+
+
+def verify(content: bytes | memoryview) -> int:
+    """Check that content is an intact dlzip2 or bzip2 stream, by decoding it
+    entirely and checking its block and stream checksums, without keeping
+    the decompressed content
+
+    Parameters
+    -----------
+    content : bytes | memoryview
+        compressed bytes, or any object supporting the buffer protocol
+
+    Returns
+    ---------
+    int
+        length of the decompressed content
+
+    Raises
+    ---------
+    Dlzip2Error
+        the same exceptions as decompress. Their block attribute is the
+        index of the block the error was found in, None when it isn't
+        in a block
+    """
+    if not isinstance(content, bytes):
+        content = _byte_view(content)
+
+    return _verify(content)
+
+
+def stream_info(content: bytes | memoryview) -> dict:
+    """Metadata of a dlzip2 stream, read from its header and end frame
+    without decompressing it, so a corrupt block isn't noticed (use verify)
+
+    Parameters
+    -----------
+    content : bytes | memoryview
+        compressed bytes, or any object supporting the buffer protocol. With
+        an mmap, only the pages of the header and end frame are read
+
+    Returns
+    ---------
+    dict
+        compressed_len, original_len, block_count, block_size, version,
+        flags (names of the pipeline stages and options) and stream_crc
+
+    Raises
+    ---------
+    Dlzip2Error
+        if content isn't a dlzip2 stream (bzip2 streams don't record these
+        values), TruncatedInputError if it doesn't end with an end frame
+    """
+    if isinstance(content, bytes):
+        return _stream_info(content)
+
+    # Released even when an error is raised, so an mmap can then be closed
+    with _byte_view(content) as view:
+        return _stream_info(view)
@@ -0,0 +1,315 @@
+import builtins
+import io
+import os
+
+from dlzip2._dlzip2 import (Dlzip2Compressor, Dlzip2Decompressor, Dlzip2Error,
+                            TruncatedInputError)
+
+_MODE_CLOSED = 0
+_MODE_READ = 1
+_MODE_WRITE = 2
+
+# Size of the pieces of compressed file given to the decompressor
+_BUFFER_SIZE = 128 * 1024
+
+
+class _DecompressReader(io.RawIOBase):
+    """Raw reader of the decompressed content of a file object, streams
+    written one after the other are read as a single content"""
+
+    def __init__(self, fp):
+        self._fp = fp
+        self._decompressor = Dlzip2Decompressor()
+        self._eof = False
+        # Position in the decompressed content
+        self._pos = 0
+        # Length of the decompressed content, -1 until the end is reached
+        self._size = -1
+
+    def readable(self):
+        return True
+
+    def seekable(self):
+        return self._fp.seekable()
+
+    def close(self):
+        self._decompressor = None
+        return super().close()
+
+    def readinto(self, b):
+        with memoryview(b) as view, view.cast("B") as byte_view:
+            data = self.read(len(byte_view))
+            byte_view[:len(data)] = data
+        return len(data)
+
+    def read(self, size=-1):
+        if size < 0:
+            return self.readall()
+        if not size or self._eof:
+            return b""
+
+        data = b""
+        while not data:
+            if self._decompressor.eof:
+                # Another stream may follow, anything else is ignored
+                raw = self._decompressor.unused_data or self._fp.read(
+                    _BUFFER_SIZE)
+                if not raw:
+                    break
+                self._decompressor = Dlzip2Decompressor()
+                try:
+                    data = self._decompressor.decompress(raw, size)
+                except Dlzip2Error:
+                    break
+            else:
+                raw = b""
+                if self._decompressor.needs_input:
+                    raw = self._fp.read(_BUFFER_SIZE)
+                    if not raw:
+                        raise TruncatedInputError(
+                            "compressed file ended before the end of the "
+                            "stream")
+                data = self._decompressor.decompress(raw, size)
+
+        if not data:
+            self._eof = True
+            self._size = self._pos
+            return b""
+        self._pos += len(data)
+        return data
+
+    def _rewind(self):
+        self._fp.seek(0)
+        self._decompressor = Dlzip2Decompressor()
+        self._eof = False
+        self._pos = 0
+
+    def seek(self, offset, whence=io.SEEK_SET):
+        if whence == io.SEEK_CUR:
+            offset += self._pos
+        elif whence == io.SEEK_END:
+            if self._size < 0:
+                while self.read(io.DEFAULT_BUFFER_SIZE):
+                    pass
+            offset += self._size
+        elif whence != io.SEEK_SET:
+            raise ValueError(f"invalid whence ({whence})")
+
+        # The content can only be decompressed from the start
+        if offset < self._pos:
+            self._rewind()
+        offset -= self._pos
+        while offset > 0:
+            data = self.read(min(io.DEFAULT_BUFFER_SIZE, offset))
+            if not data:
+                break
+            offset -= len(data)
+        return self._pos
+
+    def tell(self):
+        return self._pos
+
+
+class Dlzip2File(io.BufferedIOBase):
+    """A file object reading or writing a .dlz2 file, as bz2.BZ2File does
+    for .bz2 files. The content is de/compressed by pieces, a block at a time
+
+    Parameters
+    -----------
+    filename : str | bytes | os.PathLike | file object
+        path of the file to open, or a binary file object to read from or
+        write to (it is not closed with the Dlzip2File)
+    mode : str = 'r'
+        'r' to read, 'w' to overwrite, 'x' to create and 'a' to append
+        (a new stream is written after the existing content, and both are
+        read back as a single content). A 'b' can be added
+    multi_table : bool = False
+        when writing, code each block with several Huffman tables
+    max_code_len : int = 17
+        when writing, maximum length of a Huffman code in bits
+    threads : int = 1
+        when writing, number of blocks compressed in parallel, 0 for one
+        per cpu, capped to 4 per cpu
+
+    Seeking is supported when reading, but it is emulated : seeking
+    backward decompresses the file again from the start
+    """
+
+    def __init__(self, filename, mode='r', *, multi_table=False,
+                 max_code_len=17, threads=1):
+        self._fp = None
+        self._closefp = False
+        self._mode = _MODE_CLOSED
+
+        if mode in ('', 'r', 'rb'):
+            mode = 'rb'
+            mode_code = _MODE_READ
+        elif mode in ('w', 'wb', 'x', 'xb', 'a', 'ab'):
+            mode = mode[0] + 'b'
+            mode_code = _MODE_WRITE
+            self._compressor = Dlzip2Compressor(multi_table, max_code_len,
+                                                threads)
+            self._pos = 0
+        else:
+            raise ValueError(f"invalid mode: {mode!r}")
+
+        if isinstance(filename, (str, bytes, os.PathLike)):
+            self._fp = builtins.open(filename, mode)
+            self._closefp = True
+        elif hasattr(filename, 'read') or hasattr(filename, 'write'):
+            self._fp = filename
+        else:
+            raise TypeError("filename must be a str, bytes, file or "
+                            "PathLike object")
+        self._mode = mode_code
+
+        if self._mode == _MODE_READ:
+            self._buffer = io.BufferedReader(_DecompressReader(self._fp))
+
+    def close(self):
+        if self._mode == _MODE_CLOSED:
+            return
+        try:
+            if self._mode == _MODE_READ:
+                self._buffer.close()
+            else:
+                self._fp.write(self._compressor.flush())
+                self._compressor = None
+        finally:
+            try:
+                if self._closefp:
+                    self._fp.close()
+            finally:
+                self._fp = None
+                self._closefp = False
+                self._mode = _MODE_CLOSED
+                self._buffer = None
+
+    @property
+    def closed(self):
+        return self._mode == _MODE_CLOSED
+
+    def fileno(self):
+        self._check_not_closed()
+        return self._fp.fileno()
+
+    def seekable(self):
+        return self.readable() and self._buffer.seekable()
+
+    def readable(self):
+        self._check_not_closed()
+        return self._mode == _MODE_READ
+
+    def writable(self):
+        self._check_not_closed()
+        return self._mode == _MODE_WRITE
+
+    def _check_not_closed(self):
+        if self.closed:
+            raise ValueError("I/O operation on closed file")
+
+    def _check_can_read(self):
+        if not self.readable():
+            raise io.UnsupportedOperation("File not open for reading")
+
+    def _check_can_write(self):
+        if not self.writable():
+            raise io.UnsupportedOperation("File not open for writing")
+
+    def peek(self, n=0):
+        self._check_can_read()
+        return self._buffer.peek(n)
+
+    def read(self, size=-1):
+        self._check_can_read()
+        return self._buffer.read(size)
+
+    def read1(self, size=-1):
+        self._check_can_read()
+        if size < 0:
+            size = io.DEFAULT_BUFFER_SIZE
+        return self._buffer.read1(size)
+
+    def readinto(self, b):
+        self._check_can_read()
+        return self._buffer.readinto(b)
+
+    def readline(self, size=-1):
+        self._check_can_read()
+        return self._buffer.readline(size)
+
+    def readlines(self, size=-1):
+        self._check_can_read()
+        return self._buffer.readlines(size)
+
+    def write(self, data):
+        """Compress data and write it to the file, return the number of
+        uncompressed bytes written. Nothing may be written to the file until
+        a whole block of content has been given"""
+        self._check_can_write()
+        with memoryview(data) as view, view.cast('B') as byte_view:
+            self._fp.write(self._compressor.compress(byte_view))
+            length = len(byte_view)
+        self._pos += length
+        return length
+
+    def seek(self, offset, whence=io.SEEK_SET):
+        """Move to the offset in the decompressed content, only supported
+        when reading"""
+        if not self.seekable():
+            raise io.UnsupportedOperation("Seeking is only supported on "
+                                          "files open for reading")
+        return self._buffer.seek(offset, whence)
+
+    def tell(self):
+        """Position in the decompressed content"""
+        self._check_not_closed()
+        if self._mode == _MODE_READ:
+            return self._buffer.tell()
+        return self._pos
+
+
+def open(filename, mode='rb', *, multi_table=False, max_code_len=17,
+         threads=1, encoding=None, errors=None, newline=None):
+    """Open a .dlz2 file in binary or text mode, as bz2.open does
+
+    Parameters
+    -----------
+    filename : str | bytes | os.PathLike | file object
+        path of the file to open, or a file object to read from or write to
+    mode : str = 'rb'
+        'r', 'w', 'x' or 'a', followed by 'b' for binary mode (default) or
+        't' for text mode
+    multi_table : bool = False
+        see compress
+    max_code_len : int = 17
+        see compress
+    threads : int = 1
+        see compress
+    encoding, errors, newline
+        only in text mode, see io.TextIOWrapper
+
+    Returns
+    ---------
+    Dlzip2File | io.TextIOWrapper
+        a Dlzip2File in binary mode, wrapped in a io.TextIOWrapper in text
+        mode
+    """
+    if 't' in mode:
+        if 'b' in mode:
+            raise ValueError(f"invalid mode: {mode!r}")
+    else:
+        if encoding is not None:
+            raise ValueError("encoding is not supported in binary mode")
+        if errors is not None:
+            raise ValueError("errors is not supported in binary mode")
+        if newline is not None:
+            raise ValueError("newline is not supported in binary mode")
+
+    binary_file = Dlzip2File(filename, mode.replace('t', ''),
+                             multi_table=multi_table,
+                             max_code_len=max_code_len, threads=threads)
+
+    if 't' in mode:
+        return io.TextIOWrapper(binary_file, encoding, errors, newline)
+    return binary_file
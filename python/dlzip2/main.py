@@ -1,8 +1,301 @@
 import argparse
+import contextlib
+import glob
+import mmap
+import os
 import pathlib
 import sys
 import time
-from dlzip2 import compress, decompress
+from dlzip2 import (Dlzip2Compressor, Dlzip2Error, compress, decompress,
+                    stream_info, verify)
+from dlzip2._dlzip2 import _LIST_HEADER, _list_line, _list_totals
+from dlzip2.dlzip2_file import Dlzip2File
+
+# Size of the pieces of the input de/compressed at once
+_CHUNK_SIZE = 1 << 20
+# Path standing for the standard input
+_STDIN_PATH = pathlib.Path('-')
+_SUFFIXES = ('.dlz2', '.bz2')
+
+
+class _FileError(Exception):
+    """A file that couldn't be de/compressed, with the reason"""
+
+
+class _CountingReader:
+    """Binary file object counting the bytes read from the wrapped one"""
+
+    def __init__(self, fp):
+        self._fp = fp
+        self.count = 0
+
+    def read(self, size=-1):
+        data = self._fp.read(size)
+        self.count += len(data)
+        return data
+
+    def seekable(self):
+        return False
+
+
+class _Prefixed:
+    """Binary file object reading prefix before the content of fp"""
+
+    def __init__(self, prefix, fp):
+        self._prefix = prefix
+        self._fp = fp
+
+    def read(self, size=-1):
+        if not self._prefix:
+            return self._fp.read(size)
+        if size < 0:
+            data, self._prefix = self._prefix + self._fp.read(), b''
+            return data
+        data, self._prefix = self._prefix[:size], self._prefix[size:]
+        return data
+
+    def seekable(self):
+        return False
+
+
+def _compress_stream(input_fp, write, args):
+    """Compress input_fp by pieces, give the output to write, return the
+    number of bytes read and written"""
+    if args.bz2:
+        content = input_fp.read()
+        output = compress(content, format='bz2')
+        write(output)
+        return len(content), len(output)
+
+    compressor = Dlzip2Compressor(threads=args.threads)
+    input_len = output_len = 0
+    while True:
+        chunk = input_fp.read(_CHUNK_SIZE)
+        output = compressor.compress(chunk) if chunk else compressor.flush()
+        write(output)
+        input_len += len(chunk)
+        output_len += len(output)
+        if not chunk:
+            return input_len, output_len
+
+
+def _decompress_stream(input_fp, write):
+    """Decompress input_fp by pieces, give the output to write, return the
+    number of bytes read and written"""
+    reader = _CountingReader(input_fp)
+    signature = reader.read(3)
+    if signature == b'BZh':
+        output = decompress(signature + reader.read())
+        write(output)
+        return reader.count, len(output)
+
+    output_len = 0
+    with Dlzip2File(_Prefixed(signature, reader)) as f:
+        while chunk := f.read(_CHUNK_SIZE):
+            write(chunk)
+            output_len += len(chunk)
+    return reader.count, output_len
+
+
+def _collect_files(names, args):
+    """Files designated by names : each name is a file, a glob pattern, or a
+    directory whose files are taken with -r. In a directory, only compressed
+    files are decompressed or tested, and only the others are compressed.
+    Return the files and the messages of the names that designate none"""
+    files, errors = [], []
+    for name in names:
+        if name == '-':
+            files.append(_STDIN_PATH)
+            continue
+        # The shell expands patterns, except quoted ones or on Windows
+        if glob.has_magic(name) and not os.path.lexists(name):
+            paths = sorted(glob.glob(name, recursive=True))
+            if not paths:
+                errors.append(f"{name} doesn't match any file")
+        else:
+            paths = [name]
+
+        for path in map(pathlib.Path, paths):
+            if path.is_file():
+                files.append(path)
+            elif path.is_dir() and args.recursive:
+                compressed = args.decompress or args.test or args.list
+                files.extend(sorted(
+                    child for child in path.rglob('*') if child.is_file()
+                    and (child.suffix in _SUFFIXES) == compressed))
+            elif path.is_dir():
+                errors.append(f"{path} is a directory, use -r to process the "
+                              f"files it contains")
+            else:
+                errors.append(f"{path} is not a path to an existing file")
+
+    # A file named twice is processed once
+    return list(dict.fromkeys(files)), errors
+
+
+def _output_path(file_path, args):
+    """Path of the file the output of file_path is written to, None when it
+    only goes to the standard output"""
+    if args.output_path is not None:
+        output_path = pathlib.Path(args.output_path)
+    elif not args.stdout and file_path != _STDIN_PATH:
+        output_path = file_path
+    else:
+        return None
+
+    if args.decompress:
+        suffix = '.dlz2' if file_path == _STDIN_PATH else file_path.suffix
+        if output_path.suffix == suffix:
+            output_path = output_path.with_suffix("")
+    else:
+        suffix = '.bz2' if args.bz2 else '.dlz2'
+        if output_path.suffix != suffix:
+            output_path = output_path.with_suffix(output_path.suffix + suffix)
+    return output_path
+
+
+def _process_file(file_path, args):
+    """De/compress file_path, write the output and remove file_path with
+    --rm. Return the number of bytes read and written, raise _FileError if
+    it can't be done"""
+    if args.decompress and file_path != _STDIN_PATH and \
+            file_path.suffix not in _SUFFIXES:
+        raise _FileError(f"Cannot decompress {file_path} because it doesn't "
+                         f"have the correct extension, expected '.dlz2' or "
+                         f"'.bz2' extension")
+
+    output_path = _output_path(file_path, args)
+    if output_path and (output_path.is_file() or output_path.is_dir()):
+        raise _FileError(f"{output_path} is already a path to an existing "
+                         f"file or directory, operation aborted")
+
+    try:
+        output_files = [open(output_path, "wb")] if output_path else []
+    except OSError as err:
+        raise _FileError(f"Cannot write {output_path} : {err.strerror}")
+    if args.stdout or output_path is None:
+        output_files.append(sys.stdout.buffer)
+
+    def write(data):
+        for output_file in output_files:
+            output_file.write(data)
+
+    error = None
+    try:
+        # Content is de/compressed by pieces, so it may not fit in memory
+        with (open(file_path, 'rb') if file_path != _STDIN_PATH
+              else contextlib.nullcontext(sys.stdin.buffer)) as input_fp:
+            if args.decompress:
+                input_len, output_len = _decompress_stream(input_fp, write)
+            else:
+                input_len, output_len = _compress_stream(input_fp, write,
+                                                         args)
+    except (Dlzip2Error, OSError) as err:
+        error = err
+    finally:
+        for output_file in output_files:
+            output_file.flush()
+        if output_path:
+            output_files[0].close()
+
+    if error is not None:
+        if output_path:
+            output_path.unlink()
+        operation = "decompress" if args.decompress else "compress"
+        raise _FileError(f"Cannot {operation} {file_path} : {error}")
+
+    if args.remove and output_path and file_path != _STDIN_PATH:
+        file_path.unlink()
+    return input_len, output_len
+
+
+def _test_files(file_paths, quiet):
+    """Decode every file without writing the output, print whether it is
+    intact, return the exit code"""
+    exit_code = 0
+    for file_path in file_paths:
+        try:
+            if file_path == _STDIN_PATH:
+                verify(sys.stdin.buffer.read())
+            else:
+                with open(file_path, 'rb') as f:
+                    verify(f.read())
+        except OSError as err:
+            print(f"Cannot read {file_path} : {err.strerror}", file=sys.stderr)
+            exit_code = max(exit_code, 1)
+        except Dlzip2Error as err:
+            print(f"{file_path}: FAILED, {err}")
+            exit_code = 2
+        else:
+            if not quiet:
+                print(f"{file_path}: OK")
+    return exit_code
+
+
+def _read_stream_info(file_path):
+    """Metadata of a .dlz2 file, mapped in memory so that only its header and
+    end frame are read. The standard input is read entirely"""
+    if file_path == _STDIN_PATH:
+        return stream_info(sys.stdin.buffer.read())
+    with open(file_path, 'rb') as f:
+        # Empty files can't be mapped
+        if os.fstat(f.fileno()).st_size == 0:
+            return stream_info(b'')
+        with mmap.mmap(f.fileno(), 0, access=mmap.ACCESS_READ) as content:
+            return stream_info(content)
+
+
+def _list_files(file_paths):
+    """Print a table of the metadata of every file, without decompressing
+    them, return the exit code"""
+    exit_code = 0
+    infos = []
+    for file_path in file_paths:
+        try:
+            info = _read_stream_info(file_path)
+        except OSError as err:
+            print(f"Cannot read {file_path} : {err.strerror}", file=sys.stderr)
+            exit_code = 1
+            continue
+        except Dlzip2Error as err:
+            print(f"Cannot list {file_path} : {err}", file=sys.stderr)
+            exit_code = 1
+            continue
+
+        if not infos:
+            print(_LIST_HEADER)
+        # The lines are formatted by the extension, as in the native CLI
+        print(_list_line(info, str(file_path)))
+        infos.append(info)
+
+    if len(infos) > 1:
+        print(_list_totals(infos))
+    return exit_code
+
+
+def _print_summary(rows):
+    """Print a table of the processed files and their totals on the standard
+    error, rows hold the file, bytes read and written, time and whether it
+    succeeded"""
+    done = [row for row in rows if row[4]]
+    total = ("total", sum(row[1] for row in done),
+             sum(row[2] for row in done), sum(row[3] for row in rows),
+             f"{len(done)} ok, {len(rows) - len(done)} failed")
+    width = max(len(str(row[0])) for row in rows + [total])
+
+    print(f"{'file':<{width}}  {'in':>12}  {'out':>12}  {'ratio':>8}  "
+          f"{'time':>8}  status", file=sys.stderr)
+    for file_path, input_len, output_len, time_elapsed, status in \
+            rows + [total]:
+        if status is False:
+            sizes = f"{'-':>12}  {'-':>12}  {'-':>8}"
+        else:
+            ratio = output_len / input_len * 100 if input_len else 100
+            sizes = f"{input_len:>12}  {output_len:>12}  {ratio:>7.2f}%"
+        if isinstance(status, bool):
+            status = "ok" if status else "failed"
+        print(f"{str(file_path):<{width}}  {sizes}  {time_elapsed:>7.3f}s  "
+              f"{status}", file=sys.stderr)
 
 
 def dlzip2_cli():
@@ -14,75 +307,116 @@ def dlzip2_cli():
         epilog="This project is still under developement"
     )
 
-    parser.add_argument('filename', help="filepath to the file de/compress")
+    parser.add_argument('filenames', nargs='*', default=['-'],
+                        metavar='filename',
+                        help="files or directories to de/compress, '-' or "
+                             "nothing to read the standard input. Glob "
+                             "patterns are expanded, a summary table is "
+                             "printed when several files are processed")
     parser.add_argument('-d', '--decompress', dest="decompress",
                         action='store_true', help="flag for decompression")
+    parser.add_argument('-t', '--test', dest='test', action='store_true',
+                        help="test the integrity of compressed files: they "
+                             "are fully decompressed and their checksums "
+                             "checked, without writing any output. Each is "
+                             "reported as OK or FAILED, the exit code is 2 "
+                             "if any failed, 1 if one can't be read")
+    parser.add_argument('-l', '--list', dest='list', action='store_true',
+                        help="list the compressed and original sizes, ratio, "
+                             "number of blocks, block size, format version, "
+                             "pipeline flags and checksum recorded in .dlz2 "
+                             "files, without decompressing them")
+    parser.add_argument('-q', '--quiet', dest='quiet', action='store_true',
+                        help="only report the files that failed the test")
+    parser.add_argument('-r', '--recursive', dest='recursive',
+                        action='store_true',
+                        help="process the files of the directories given, "
+                             "and of their subdirectories: the compressed "
+                             "ones (.dlz2 or .bz2) with -d, -t or -l, the "
+                             "others otherwise")
+    keep_group = parser.add_mutually_exclusive_group()
+    keep_group.add_argument('-k', '--keep', dest='remove',
+                            action='store_false',
+                            help="keep the input files (default)")
+    keep_group.add_argument('--rm', dest='remove', action='store_true',
+                            help="remove each input file once its output "
+                                 "file is written")
+    parser.set_defaults(remove=False)
     parser.add_argument('-o', '--output',
                         dest="output_path",
                         help="path to store the de/compressed file, "
                              "if not specified, it will add/remove .dlz2 "
                              "extension from {filepath} and use it to store "
-                             "the output")
+                             "the output. Only with a single file")
+    parser.add_argument('--bz2', dest='bz2', action='store_true',
+                        help="compress in bzip2 format (.bz2 extension), "
+                             "readable by bunzip2. Decompression recognises "
+                             "both formats")
+    parser.add_argument('-T', '--threads', dest='threads', type=int,
+                        default=1,
+                        help="number of blocks compressed in parallel, 0 for "
+                             "one per cpu, capped to 4 per cpu (dlz2 format "
+                             "only)")
     parser.add_argument('-v', '--verbose', dest='verbose',
                         action='store_true',
                         help="Output the characteristics of the compression "
                              "or decompression, time and de/compression ratio")
-    parser.add_argument('-p', '--paste', dest='paste',
+    parser.add_argument('-c', '--stdout', '-p', '--paste', dest='stdout',
                         action='store_true',
-                        help='Output the de/compression to the terminal to '
-                             'pipe into other command. If no output path are '
-                             'specified, it will not generate a default '
-                             'output file')
+                        help='Write the raw de/compressed bytes to the '
+                             'standard output, to pipe them into another '
+                             'command. If no output path are specified, it '
+                             'will not generate a default output file. This '
+                             'is the default when reading the standard input. '
+                             'Only with a single file')
 
     args = parser.parse_args()
-    file_path = pathlib.Path(args.filename)
-
-    if args.output_path is not None:
-        output_path = pathlib.Path(args.output_path)
-    elif not args.paste:
-        output_path = file_path
-    else:
-        output_path = None
 
-    if not file_path.is_file():
-        print(f"{file_path} is not a path to an existing file")
+    if args.threads < 0:
+        print("--threads should be positive, or 0 for one per cpu",
+              file=sys.stderr)
         sys.exit(1)
 
-    if args.decompress and file_path.suffix != '.dlz2':
-        print(f"Cannot decompress {file_path} because it doesn't have the "
-              f"correct extension, expected '.dlz2' extension")
-        sys.exit(1)
+    file_paths, errors = _collect_files(args.filenames, args)
+    for message in errors:
+        print(message, file=sys.stderr)
+    exit_code = 1 if errors else 0
 
-    if output_path:
-        if not args.decompress and output_path.suffix != '.dlz2':
-            output_path = output_path.with_suffix(output_path.suffix + '.dlz2')
+    if args.list:
+        sys.exit(max(exit_code, _list_files(file_paths)))
+    if args.test:
+        sys.exit(max(exit_code, _test_files(file_paths, args.quiet)))
 
-        if args.decompress and output_path.suffix == '.dlz2':
-            output_path = output_path.with_suffix("")
+    if len(file_paths) > 1 and (args.stdout or args.output_path is not None):
+        parser.error("-c and -o can only be used with a single file")
 
-        if output_path.is_file() or output_path.is_dir():
-            print(f"{output_path} is already a path to an existing file or "
-                  f"directory, operation aborted")
-            sys.exit(1)
+    to_stdout = args.stdout or \
+        (_STDIN_PATH in file_paths and args.output_path is None)
+    if to_stdout and not args.decompress and sys.stdout.isatty():
+        print("Compressed data won't be written to a terminal, redirect the "
+              "standard output", file=sys.stderr)
+        sys.exit(1)
 
-    with open(file_path, 'rb') as f:
-        file_content = f.read()
+    rows = []
+    for file_path in file_paths:
         deb = time.perf_counter()
-        if args.decompress:
-            output = decompress(file_content, return_type='bytes')
-        else:
-            output = compress(file_content)
+        try:
+            input_len, output_len = _process_file(file_path, args)
+        except _FileError as err:
+            print(err, file=sys.stderr)
+            exit_code = 1
+            rows.append((file_path, 0, 0, time.perf_counter() - deb, False))
+            continue
         time_elapsed = time.perf_counter() - deb
+        rows.append((file_path, input_len, output_len, time_elapsed, True))
 
-    if args.verbose:
-        print(f"Time elapsed : {time_elapsed:.3f}s, compression/decompression "
-              f"ratio : {len(output) / len(file_content) * 100:.2f}%")
-
-    if args.paste:
-        print(output)
+        if args.verbose:
+            ratio = output_len / input_len * 100 if input_len else 100
+            print(f"{file_path} : time elapsed : {time_elapsed:.3f}s, "
+                  f"compression/decompression ratio : {ratio:.2f}%",
+                  file=sys.stderr)
 
-    if output_path:
-        with open(output_path, "wb") as f:
-            f.write(output)
+    if len(rows) > 1:
+        _print_summary(rows)
 
-    sys.exit(0)
+    sys.exit(exit_code)
@@ -1,5 +1,16 @@
+import array
+import bz2
+import mmap
+import os
+import random
+import time
+from concurrent.futures import ThreadPoolExecutor
+
 import pytest
-from dlzip2.compression_utils import compress, decompress
+from dlzip2 import (ChecksumError, CorruptDataError, Dlzip2Compressor,
+                    Dlzip2Decompressor, Dlzip2Error, TruncatedInputError)
+from dlzip2.compression_utils import (compress, decompress, stream_info,
+                                      verify)
 
 
 def test_compress_argument():
@@ -7,13 +18,17 @@ def test_compress_argument():
         compress({})
     with pytest.raises(ValueError):
         compress([1111])
+    with pytest.raises(ValueError):
+        compress(b'blabla', format='zip')
 
 
 def test_decompress_argument():
     with pytest.raises(TypeError):
         decompress("blabla")
     with pytest.raises(ValueError):
-        decompress(b'blabla', "test")
+        decompress(b'blabla', return_type="test")
+    with pytest.raises(Dlzip2Error):
+        decompress(b'blabla')
 
 
 def test_compress_decompress():
@@ -24,3 +39,245 @@ def test_compress_decompress():
     content_decompressed = decompress(content_compressed, return_type='str')
 
     assert content_decompressed == content
+
+
+def test_decompress_corrupt():
+    content_compressed = compress("Some useless text that will be corrupted")
+
+    for idx in range(len(content_compressed)):
+        corrupted = bytearray(content_compressed)
+        corrupted[idx] ^= 0xff
+        with pytest.raises(Dlzip2Error):
+            decompress(bytes(corrupted))
+
+    with pytest.raises(TruncatedInputError):
+        decompress(content_compressed[:-1])
+    # The stream checksum follows the tag of the 21 bytes end frame
+    corrupted = bytearray(content_compressed)
+    corrupted[-20] ^= 0xff
+    with pytest.raises(ChecksumError):
+        decompress(bytes(corrupted))
+    # The tag of the block frame follows the 22 bytes of header
+    corrupted = bytearray(content_compressed)
+    corrupted[22] ^= 0xff
+    with pytest.raises(CorruptDataError):
+        decompress(bytes(corrupted))
+    assert issubclass(Dlzip2Error, ValueError)
+
+
+def test_verify():
+    content = b"Some useless text that should be verified" * 1000
+    content_compressed = compress(content)
+    assert verify(content_compressed) == len(content)
+    assert verify(bytearray(compress(content, format='bz2'))) == len(content)
+
+    # The data of the block follows the 22 bytes of header and the 17 of its
+    # frame
+    corrupted = bytearray(content_compressed)
+    corrupted[22 + 17] ^= 0xff
+    with pytest.raises(Dlzip2Error) as error:
+        verify(corrupted)
+    assert error.value.block == 0
+    assert "in block 0" in str(error.value)
+
+    with pytest.raises(TruncatedInputError) as error:
+        verify(content_compressed[:-1])
+    assert error.value.block is None
+    corrupted = bytearray(content_compressed)
+    corrupted[-20] ^= 0xff
+    with pytest.raises(ChecksumError) as error:
+        verify(corrupted)
+    assert error.value.block is None
+
+
+def test_stream_info(tmp_path):
+    content = b"Some useless text that should be listed" * 1000
+    content_compressed = compress(content, multi_table=True)
+    info = stream_info(content_compressed)
+    assert info['compressed_len'] == len(content_compressed)
+    assert info['original_len'] == len(content)
+    assert info['block_count'] == 1
+    assert info['flags'] == ['bwt', 'mtf', 'zrle', 'huffman', 'multi-table']
+
+    compressor = Dlzip2Compressor()
+    stream = compressor.compress(content) + compressor.flush()
+    assert stream_info(bytearray(stream))['original_len'] == len(content)
+
+    path = tmp_path / "file.dlz2"
+    path.write_bytes(content_compressed)
+    with open(path, 'rb') as f, \
+            mmap.mmap(f.fileno(), 0, access=mmap.ACCESS_READ) as mapped:
+        assert stream_info(mapped) == info
+
+    with pytest.raises(TruncatedInputError):
+        stream_info(content_compressed[:-1])
+    with pytest.raises(Dlzip2Error):
+        stream_info(compress(content, format='bz2'))
+
+
+def test_buffer_protocol(tmp_path):
+    content = b"Some useless text that should be compressed from a buffer"
+    content_compressed = compress(content)
+
+    for buffer in (bytearray(content), memoryview(content),
+                   array.array('B', content)):
+        assert compress(buffer) == content_compressed
+        assert compress(buffer, format='bz2') == compress(content,
+                                                          format='bz2')
+    assert decompress(bytearray(content_compressed)) == content
+    assert decompress(memoryview(b'..' + content_compressed)[2:]) == content
+
+    path = tmp_path / "file.dlz2"
+    path.write_bytes(content_compressed)
+    with open(path, 'rb') as f, mmap.mmap(f.fileno(), 0,
+                                          access=mmap.ACCESS_READ) as m:
+        assert decompress(m) == content
+
+    # Buffers of larger items are compressed as their bytes
+    words = array.array('H', [1, 2, 3])
+    assert decompress(compress(words)) == words.tobytes()
+    with pytest.raises(TypeError):
+        compress(memoryview(content)[::2])
+    assert isinstance(compress(content), bytes)
+    assert isinstance(decompress(content_compressed), bytes)
+
+
+def test_compress_multi_table():
+    content = ("Some useless text that should be compressed with several "
+               "Huffman tables, ") * 100
+
+    content_compressed = compress(content, multi_table=True)
+    assert len(content_compressed) < len(compress(content))
+    assert decompress(content_compressed, return_type='str') == content
+
+
+def test_compress_threads():
+    content = bytes(random.Random(0).choices(range(40), k=1_300_000))
+
+    content_compressed = compress(content)
+    for threads in (0, 2, 3):
+        assert compress(content, threads=threads) == content_compressed
+    assert decompress(content_compressed) == content
+
+    streams = []
+    for threads in (1, 2):
+        compressor = Dlzip2Compressor(threads=threads)
+        stream = b"".join(compressor.compress(content[i:i + 100_000])
+                          for i in range(0, len(content), 100_000))
+        streams.append(stream + compressor.flush())
+    assert streams[0] == streams[1]
+    assert decompress(streams[1]) == content
+
+    with pytest.raises(ValueError):
+        compress(content, threads=-1)
+    # Capped to 4 threads per cpu, as each holds a block
+    assert compress(content, threads=100_000) == content_compressed
+    compressor = Dlzip2Compressor(threads=100_000)
+    assert compressor.compress(content) + compressor.flush() == streams[0]
+
+
+def test_compress_max_code_len():
+    content = bytes(b for i in range(20) for b in [i] * (1 << (i % 12)))
+
+    content_compressed = compress(content, max_code_len=9)
+    assert decompress(content_compressed) == content
+
+    with pytest.raises(ValueError):
+        compress(content, max_code_len=30)
+
+
+def test_bz2_format():
+    content = b"Some useless text that should be compressed in bzip2 format"
+
+    content_compressed = compress(content, format='bz2')
+    assert bz2.decompress(content_compressed) == content
+    assert decompress(content_compressed) == content
+    assert decompress(bz2.compress(content)) == content
+
+
+def test_compressor():
+    content = b"Some useless text that should be compressed by pieces. " * 20000
+
+    compressor = Dlzip2Compressor()
+    chunks = [compressor.compress(content[i:i + 100_000])
+              for i in range(0, len(content), 100_000)]
+    chunks.append(compressor.flush())
+    # Only the header is written before the first block is full, then full
+    # blocks are returned as soon as they are compressed
+    assert chunks[0].startswith(b'DLZ2') and len(chunks[0]) == 22
+    assert any(chunks[1:-1])
+    assert decompress(b''.join(chunks)) == content
+
+    with pytest.raises(ValueError):
+        compressor.flush()
+    with pytest.raises(ValueError):
+        compressor.compress(b'more')
+    with pytest.raises(ValueError):
+        Dlzip2Compressor(max_code_len=30)
+
+
+def test_decompressor():
+    content = b"Some useless text that should be decompressed by pieces. " * 20
+    content_compressed = compress(content)
+
+    decompressor = Dlzip2Decompressor()
+    assert decompressor.needs_input
+    result = b''.join(decompressor.decompress(content_compressed[i:i + 10])
+                      for i in range(0, len(content_compressed), 10))
+    assert result == content
+    assert decompressor.eof
+    assert decompressor.unused_data == b''
+    with pytest.raises(EOFError):
+        decompressor.decompress(b'')
+
+    # max_length and data after the end of the stream, as with bz2
+    decompressor = Dlzip2Decompressor()
+    result = decompressor.decompress(content_compressed + b'trailing', 100)
+    assert result == content[:100]
+    assert not decompressor.needs_input and not decompressor.eof
+    result += decompressor.decompress(b'')
+    assert result == content
+    assert decompressor.eof
+    assert decompressor.unused_data == b'trailing'
+
+    with pytest.raises(Dlzip2Error):
+        Dlzip2Decompressor().decompress(b'BZh9')
+    corrupted = bytearray(content_compressed)
+    corrupted[-20] ^= 0xff
+    with pytest.raises(ChecksumError):
+        Dlzip2Decompressor().decompress(bytes(corrupted))
+
+
+def _thread_contents():
+    random.seed(0)
+    return [bytes(random.choice(b"some useless text") for _ in range(500_000))
+            for _ in range(4)]
+
+
+def _run_threads(function, arguments, workers):
+    """Results of function on arguments from a pool of workers, and the wall
+    time taken"""
+    start = time.perf_counter()
+    with ThreadPoolExecutor(workers) as executor:
+        results = list(executor.map(function, arguments))
+    return results, time.perf_counter() - start
+
+
+def test_threads():
+    contents = _thread_contents()
+    compressed, _ = _run_threads(compress, contents, len(contents))
+    results, _ = _run_threads(decompress, compressed, len(contents))
+    assert results == contents
+
+
+@pytest.mark.skipif(not os.environ.get("DLZIP2_BENCH"),
+                    reason="timing benchmark, run with DLZIP2_BENCH=1")
+def test_threads_speedup():
+    if (os.cpu_count() or 1) < 2:
+        pytest.skip("needs several cpus to measure a speedup")
+    contents = _thread_contents()
+    # The GIL is released during the de/compression, so threads run in
+    # parallel instead of one after the other
+    _, sequential = _run_threads(compress, contents, 1)
+    _, threaded = _run_threads(compress, contents, len(contents))
+    assert threaded < sequential * 0.75
@@ -0,0 +1,97 @@
+import io
+
+import pytest
+from dlzip2 import Dlzip2File, TruncatedInputError, compress, decompress, open
+
+
+def test_open_binary(tmp_path):
+    content = b"Some useless text that should be written to a file. " * 1000
+    path = tmp_path / "file.dlz2"
+
+    with open(path, 'wb') as f:
+        f.write(content[:1000])
+        f.write(bytearray(content[1000:]))
+        assert f.tell() == len(content)
+    assert decompress(path.read_bytes()) == content
+
+    with open(path) as f:
+        assert f.read(10) == content[:10]
+        assert f.read() == content[10:]
+        assert f.read() == b''
+
+    with pytest.raises(FileExistsError):
+        open(path, 'x')
+    with pytest.raises(ValueError):
+        open(path, 'rw')
+    with pytest.raises(ValueError):
+        open(path, 'rb', encoding='utf8')
+
+
+def test_open_text(tmp_path):
+    lines = [f"line {i} of some useless text\n" for i in range(1000)]
+    path = tmp_path / "file.dlz2"
+
+    with open(path, 'wt', encoding='utf8') as f:
+        f.writelines(lines)
+
+    with open(path, 'rt', encoding='utf8') as f:
+        assert f.readline() == lines[0]
+        assert list(f) == lines[1:]
+
+    with open(path, 'rb') as f:
+        assert f.readlines() == [line.encode() for line in lines]
+
+
+def test_append(tmp_path):
+    path = tmp_path / "file.dlz2"
+
+    with open(path, 'w', multi_table=True) as f:
+        f.write(b"first stream\n")
+    with open(path, 'a') as f:
+        f.write(b"second stream\n")
+
+    with open(path) as f:
+        assert f.read() == b"first stream\nsecond stream\n"
+
+
+def test_seek():
+    content = bytes(range(256)) * 2000
+    fp = io.BytesIO(compress(content))
+
+    with Dlzip2File(fp) as f:
+        assert f.seekable()
+        assert f.seek(1000) == 1000
+        assert f.read(10) == content[1000:1010]
+        # Seeking backward decompresses the file again
+        assert f.seek(-20, io.SEEK_CUR) == 990
+        assert f.read(10) == content[990:1000]
+        assert f.seek(-5, io.SEEK_END) == len(content) - 5
+        assert f.read() == content[-5:]
+        assert f.seek(0) == 0
+        assert f.read() == content
+    # A file object given is left open
+    assert not fp.closed
+
+    with Dlzip2File(io.BytesIO(), 'w') as f:
+        assert not f.seekable()
+        with pytest.raises(io.UnsupportedOperation):
+            f.seek(0)
+        with pytest.raises(io.UnsupportedOperation):
+            f.read()
+
+
+def test_file_invalid():
+    content_compressed = compress(b"Some useless text")
+
+    with Dlzip2File(io.BytesIO(content_compressed[:-1])) as f:
+        with pytest.raises(TruncatedInputError):
+            f.read()
+    # Data after the last stream is ignored, as bz2 does
+    with Dlzip2File(io.BytesIO(content_compressed + b'\x00' * 10)) as f:
+        assert f.read() == b"Some useless text"
+
+    f = Dlzip2File(io.BytesIO(content_compressed))
+    f.close()
+    assert f.closed
+    with pytest.raises(ValueError):
+        f.read()
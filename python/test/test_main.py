@@ -0,0 +1,148 @@
+import bz2
+import subprocess
+import sys
+
+from dlzip2 import compress, decompress
+
+_CLI = [sys.executable, '-c', 'import dlzip2; dlzip2._cli()']
+
+
+def run_cli(*args, stdin=b""):
+    return subprocess.run(_CLI + list(args), input=stdin, capture_output=True)
+
+
+def test_cli_stdin():
+    content = b"Some useless text that should go through a pipe. " * 30000
+
+    compressed = run_cli(stdin=content)
+    assert compressed.returncode == 0
+    assert decompress(compressed.stdout) == content
+    assert run_cli('-', '-d', stdin=compressed.stdout).stdout == content
+
+    compressed = run_cli('--bz2', '-', stdin=content)
+    assert bz2.decompress(compressed.stdout) == content
+    assert run_cli('-d', stdin=compressed.stdout).stdout == content
+
+    result = run_cli('-d', stdin=compressed.stdout[:-10])
+    assert result.returncode == 1
+    assert result.stdout == b""
+    assert run_cli('-d', stdin=b"not compressed").returncode == 1
+
+
+def test_cli_stdout(tmp_path):
+    path = tmp_path / "file.txt"
+    content = bytes(range(256)) * 100
+    path.write_bytes(content)
+
+    compressed = run_cli(str(path), '-c')
+    assert compressed.returncode == 0
+    assert decompress(compressed.stdout) == content
+    # Nothing is written next to the input
+    assert list(tmp_path.iterdir()) == [path]
+
+    compressed_path = tmp_path / "file.txt.dlz2"
+    compressed_path.write_bytes(compressed.stdout)
+    assert run_cli(str(compressed_path), '-d', '-c').stdout == content
+
+
+def test_cli_test(tmp_path):
+    content = b"Some useless text that should be tested. " * 1000
+    paths = [tmp_path / name for name in ("a.dlz2", "b.bz2", "c.dlz2")]
+    paths[0].write_bytes(compress(content))
+    paths[1].write_bytes(compress(content, format='bz2'))
+    paths[2].write_bytes(compress(content)[:-1] + b'\x00')
+
+    result = run_cli('-t', *map(str, paths[:2]))
+    assert result.returncode == 0
+    assert result.stdout.decode().splitlines() == [f"{paths[0]}: OK",
+                                                   f"{paths[1]}: OK"]
+
+    result = run_cli('-tq', *map(str, paths))
+    assert result.returncode == 2
+    assert result.stdout.decode().startswith(f"{paths[2]}: FAILED, ")
+    # Nothing is written or removed
+    assert sorted(tmp_path.iterdir()) == paths
+
+    assert run_cli('-t', stdin=paths[0].read_bytes()).returncode == 0
+    assert run_cli('-t', str(tmp_path / "missing.dlz2")).returncode == 1
+
+
+def test_cli_batch(tmp_path):
+    contents = {"a.txt": b"Some useless text. " * 1000,
+                "sub/b.txt": bytes(range(256)) * 100,
+                "sub/deep/c.log": b"tiny"}
+    for name, content in contents.items():
+        (tmp_path / name).parent.mkdir(parents=True, exist_ok=True)
+        (tmp_path / name).write_bytes(content)
+
+    # Directories need -r
+    result = run_cli(str(tmp_path))
+    assert result.returncode == 1
+    assert b"-r" in result.stderr
+
+    result = run_cli('-r', str(tmp_path))
+    assert result.returncode == 0
+    summary = result.stderr.decode()
+    assert "total" in summary and "3 ok, 0 failed" in summary
+    for name, content in contents.items():
+        assert decompress((tmp_path / (name + ".dlz2")).read_bytes()) == content
+        # Originals are kept by default
+        assert (tmp_path / name).read_bytes() == content
+
+    # Glob patterns are expanded, --rm removes the originals
+    result = run_cli('--rm', '--bz2', str(tmp_path / "**" / "*.txt"))
+    assert result.returncode == 0
+    assert not (tmp_path / "a.txt").exists()
+    assert bz2.decompress((tmp_path / "a.txt.bz2").read_bytes()) == \
+        contents["a.txt"]
+    assert run_cli(str(tmp_path / "*.none")).returncode == 1
+
+    # Only compressed files are decompressed, failures don't stop the others
+    result = run_cli('-d', '-r', '--rm', str(tmp_path / "sub"))
+    assert result.returncode == 1
+    assert b"1 ok, 2 failed" in result.stderr
+    assert not (tmp_path / "sub" / "b.txt.bz2").exists()
+    assert (tmp_path / "sub" / "b.txt").read_bytes() == contents["sub/b.txt"]
+
+    assert run_cli('-t', '-r', str(tmp_path)).returncode == 0
+    assert run_cli('-c', str(tmp_path / "a.txt.dlz2"),
+                   str(tmp_path / "a.txt.bz2")).returncode == 2
+
+
+def test_cli_list(tmp_path):
+    content = b"Some useless text that should be listed. " * 1000
+    paths = [tmp_path / "a.dlz2", tmp_path / "b.dlz2"]
+    paths[0].write_bytes(compress(content))
+    paths[1].write_bytes(compress(content, multi_table=True))
+
+    result = run_cli('-l', *map(str, paths))
+    assert result.returncode == 0
+    header, *lines, totals = result.stdout.decode().splitlines()
+    assert header.split() == ["compressed", "uncompressed", "ratio", "blocks",
+                              "block", "size", "version", "flags", "crc",
+                              "name"]
+    columns = lines[1].split()
+    assert columns[1:] == [str(len(content)), columns[2], "1", "500000", "2",
+                           "bwt,mtf,zrle,huffman,multi-table", columns[7],
+                           str(paths[1])]
+    assert columns[0] == str(paths[1].stat().st_size)
+    assert totals.split()[1] == str(2 * len(content))
+    assert totals.endswith("(totals)")
+    # Columns line up
+    assert {line.index(str(paths[0].parent)) for line in lines} == \
+        {header.index("name")} == {totals.index("(totals)")}
+
+    assert run_cli('-l', stdin=paths[0].read_bytes()).returncode == 0
+    paths[0].write_bytes(paths[0].read_bytes()[:-1])
+    result = run_cli('-l', *map(str, paths))
+    assert result.returncode == 1
+    assert len(result.stdout.decode().splitlines()) == 2
+
+
+def test_cli_threads():
+    content = b"Some useless text that should go through a pipe. " * 1000
+    assert decompress(run_cli('-T', '2', stdin=content).stdout) == content
+    assert decompress(run_cli('-T', '100000', stdin=content).stdout) == content
+    result = run_cli('-T', '-1', stdin=content)
+    assert result.returncode == 1
+    assert result.stdout == b""
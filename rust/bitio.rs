@@ -0,0 +1,313 @@
+//! Bit level I/O shared by the Huffman coders, the container and the bzip2
+//! framing. Values are packed most significant bit first, as bzip2 does.
+
+use crate::error::DlzipError;
+
+/// Pack values MSB first into bytes
+pub struct BitWriter {
+    buffer: Vec<u8>,
+    acc: u64,
+    n_bits: u32,
+}
+
+impl Default for BitWriter {
+    fn default() -> Self {
+        BitWriter::new()
+    }
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter {
+            buffer: Vec::new(),
+            acc: 0,
+            n_bits: 0,
+        }
+    }
+
+    /// Write the n lowest bits of value, n must be at most 32
+    pub fn write_bits(&mut self, n: u32, value: u32) {
+        debug_assert!(n <= 32);
+        if n == 0 {
+            return;
+        }
+        let mask = (1u64 << n) - 1;
+        self.acc = (self.acc << n) | (value as u64 & mask);
+        self.n_bits += n;
+
+        while self.n_bits >= 8 {
+            self.n_bits -= 8;
+            self.buffer.push((self.acc >> self.n_bits) as u8);
+        }
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        self.write_bits(1, bit as u32);
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bits(32, (value >> 32) as u32);
+        self.write_bits(32, value as u32);
+    }
+
+    /// Write whole bytes, the writer doesn't need to be aligned
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.n_bits == 0 {
+            self.buffer.extend_from_slice(bytes);
+        } else {
+            for &byte in bytes.iter() {
+                self.write_bits(8, byte as u32);
+            }
+        }
+    }
+
+    /// Pad with zeros up to the next byte boundary
+    pub fn align_to_byte(&mut self) {
+        if self.n_bits > 0 {
+            let pad = 8 - self.n_bits;
+            self.write_bits(pad, 0);
+        }
+    }
+
+    /// Number of bits written so far
+    pub fn position(&self) -> usize {
+        self.buffer.len() * 8 + self.n_bits as usize
+    }
+
+    /// Pad the last byte with zeros and return the packed bytes
+    pub fn finish(mut self) -> Vec<u8> {
+        self.align_to_byte();
+        self.buffer
+    }
+}
+
+/// Read values MSB first from bytes, through a 64 bits buffer
+pub struct BitReader<'a> {
+    content: &'a [u8],
+    /// Index in content of the next byte to load in the buffer
+    next_byte: usize,
+    /// Buffered bits, aligned on the most significant bit
+    acc: u64,
+    n_bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(content: &'a [u8]) -> Self {
+        BitReader {
+            content,
+            next_byte: 0,
+            acc: 0,
+            n_bits: 0,
+        }
+    }
+
+    /// Load whole bytes in the buffer until it is full or content is exhausted
+    fn refill(&mut self) {
+        while self.n_bits <= 56 && self.next_byte < self.content.len() {
+            self.acc |= (self.content[self.next_byte] as u64) << (56 - self.n_bits);
+            self.next_byte += 1;
+            self.n_bits += 8;
+        }
+    }
+
+    /// Next n bits without consuming them, n must be at most 32. Past the end
+    /// of content the missing bits are read as zeros
+    pub fn peek_bits(&mut self, n: u32) -> u32 {
+        debug_assert!(n <= 32);
+        if n == 0 {
+            return 0;
+        }
+        if self.n_bits < n {
+            self.refill();
+        }
+        (self.acc >> (64 - n)) as u32
+    }
+
+    /// Drop the next n bits, n must be at most 32
+    pub fn consume(&mut self, n: u32) -> Result<(), DlzipError> {
+        debug_assert!(n <= 32);
+        if self.n_bits < n {
+            self.refill();
+            if self.n_bits < n {
+                return Err(DlzipError::Truncated);
+            }
+        }
+        self.acc <<= n;
+        self.n_bits -= n;
+        Ok(())
+    }
+
+    /// Read n bits as an integer, n must be at most 32
+    pub fn read_bits(&mut self, n: u32) -> Result<u32, DlzipError> {
+        let value = self.peek_bits(n);
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool, DlzipError> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, DlzipError> {
+        let high = self.read_bits(32)? as u64;
+        Ok(high << 32 | self.read_bits(32)? as u64)
+    }
+
+    /// Read n whole bytes, the reader must be byte aligned
+    pub fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], DlzipError> {
+        debug_assert!(self.is_aligned());
+        let start = self.position() / 8;
+        if self.content.len() - start < n {
+            return Err(DlzipError::Truncated);
+        }
+        self.next_byte = start + n;
+        self.acc = 0;
+        self.n_bits = 0;
+        Ok(&self.content[start..start + n])
+    }
+
+    /// Skip the padding bits up to the next byte boundary
+    pub fn align_to_byte(&mut self) {
+        let padding = self.n_bits % 8;
+        self.acc <<= padding;
+        self.n_bits -= padding;
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.n_bits.is_multiple_of(8)
+    }
+
+    /// Number of bits read so far
+    pub fn position(&self) -> usize {
+        self.next_byte * 8 - self.n_bits as usize
+    }
+
+    /// Bytes of content after the current (byte aligned) position
+    pub fn remaining_bytes(&self) -> &'a [u8] {
+        &self.content[self.position().div_ceil(8)..]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BitReader, BitWriter};
+    use crate::error::DlzipError;
+
+    #[test]
+    fn test_bit_writer_reader() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(3, 0b101);
+        writer.write_bit(true);
+        writer.write_bits(24, 0x31_4159);
+        writer.write_bits(32, 0xdead_beef);
+        let content = writer.finish();
+
+        assert_eq!(content.len(), 8);
+        assert_eq!(content[0], 0b1011_0011);
+
+        let mut reader = BitReader::new(&content);
+        assert_eq!(reader.read_bits(3), Ok(0b101));
+        assert_eq!(reader.read_bit(), Ok(true));
+        assert_eq!(reader.read_bits(24), Ok(0x31_4159));
+        assert_eq!(reader.read_bits(32), Ok(0xdead_beef));
+        reader.align_to_byte();
+        assert_eq!(reader.read_bits(1), Err(DlzipError::Truncated));
+    }
+
+    #[test]
+    fn test_all_widths_and_offsets() {
+        // Every width written after every offset in a byte
+        for offset in 0..8 {
+            for n in 0..=32 {
+                let value = 0xa5c3_f00f_u32.checked_shr(32 - n).unwrap_or(0);
+                let mut writer = BitWriter::new();
+                writer.write_bits(offset, 0x7f);
+                writer.write_bits(n, value);
+                writer.write_bits(n, !value);
+                writer.write_bit(true);
+                assert_eq!(writer.position(), (offset + 2 * n + 1) as usize);
+                let content = writer.finish();
+                assert_eq!(content.len(), (offset + 2 * n + 1).div_ceil(8) as usize);
+
+                let mut reader = BitReader::new(&content);
+                assert_eq!(reader.read_bits(offset), Ok(0x7f >> (7 - offset)));
+                assert_eq!(reader.peek_bits(n), value);
+                assert_eq!(reader.read_bits(n), Ok(value));
+                let mask = 1u32.checked_shl(n).map_or(u32::MAX, |bit| bit - 1);
+                assert_eq!(reader.read_bits(n), Ok(!value & mask));
+                assert_eq!(reader.read_bit(), Ok(true));
+                assert_eq!(reader.position(), (offset + 2 * n + 1) as usize);
+            }
+        }
+    }
+
+    #[test]
+    fn test_values_larger_than_width() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(4, 0xfff5);
+        writer.write_bits(4, 0);
+        assert_eq!(writer.finish(), vec![0x50]);
+    }
+
+    #[test]
+    fn test_u64_and_bytes() {
+        let mut writer = BitWriter::new();
+        writer.write_u64(0x0123_4567_89ab_cdef);
+        writer.write_bytes(b"ab");
+        writer.write_bits(4, 0xf);
+        writer.write_bytes(b"c");
+        writer.align_to_byte();
+        assert_eq!(writer.position(), 8 * 12);
+        writer.write_bytes(b"d");
+        let content = writer.finish();
+        assert_eq!(&content[8..], &[b'a', b'b', 0xf6, 0x30, b'd']);
+
+        let mut reader = BitReader::new(&content);
+        assert_eq!(reader.read_u64(), Ok(0x0123_4567_89ab_cdef));
+        assert_eq!(reader.read_bytes(2), Ok(&b"ab"[..]));
+        assert_eq!(reader.read_bits(4), Ok(0xf));
+        assert_eq!(reader.read_bits(8), Ok(b'c' as u32));
+        assert!(!reader.is_aligned());
+        reader.align_to_byte();
+        assert!(reader.is_aligned());
+        assert_eq!(reader.position(), 8 * 12);
+        assert_eq!(reader.read_bytes(2), Err(DlzipError::Truncated));
+        assert_eq!(reader.read_bytes(1), Ok(&b"d"[..]));
+        assert_eq!(reader.read_bytes(0), Ok(&b""[..]));
+        assert_eq!(reader.read_u64(), Err(DlzipError::Truncated));
+    }
+
+    #[test]
+    fn test_bit_reader_peek() {
+        let content = [0b1010_0110, 0xff, 0x01];
+        let mut reader = BitReader::new(&content);
+
+        assert_eq!(reader.peek_bits(4), 0b1010);
+        assert_eq!(reader.peek_bits(12), 0b1010_0110_1111);
+        assert_eq!(reader.read_bits(3), Ok(0b101));
+        assert_eq!(reader.peek_bits(8), 0b0011_0111);
+        assert_eq!(reader.remaining_bytes(), &content[1..]);
+
+        // Missing bits are peeked as zeros but can't be consumed
+        assert_eq!(reader.read_bits(13), Ok(0b0_0110_1111_1111));
+        assert_eq!(reader.peek_bits(16), 0x0100);
+        assert_eq!(reader.consume(16), Err(DlzipError::Truncated));
+        assert_eq!(reader.consume(8), Ok(()));
+        assert_eq!(reader.peek_bits(1), 0);
+        assert_eq!(reader.remaining_bytes(), &[] as &[u8]);
+    }
+
+    #[test]
+    fn test_read_past_end() {
+        let content = [0xff; 9];
+        let mut reader = BitReader::new(&content);
+        assert_eq!(reader.read_bits(32), Ok(u32::MAX));
+        assert_eq!(reader.read_bits(32), Ok(u32::MAX));
+        // A failed read doesn't consume anything
+        assert_eq!(reader.read_bits(9), Err(DlzipError::Truncated));
+        assert_eq!(reader.position(), 64);
+        assert_eq!(reader.read_bits(8), Ok(0xff));
+        assert_eq!(reader.read_bit(), Err(DlzipError::Truncated));
+        assert_eq!(BitReader::new(&[]).read_bits(0), Ok(0));
+    }
+}
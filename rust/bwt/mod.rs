@@ -1,12 +1,17 @@
-pub const BWT_MARKER: usize = 256;
+use std::cmp::Ordering;
+
+use crate::error::DlzipError;
+
+pub const BWT_MARKER: u16 = 256;
+/// Default number of symbols in a block
 pub const MAX_VEC_SIZE: usize = 500_000;
 
 mod sais;
-use sais::sais_u8;
+use sais::sais;
 
 #[allow(dead_code)]
 fn counting_sort(
-    content: &[usize],
+    content: &[u16],
     list_rotation: &mut [isize],
     n_col: isize,
     get_char: GetCharFn,
@@ -16,7 +21,7 @@ fn counting_sort(
     let mut occurences: [usize; 257] = [0; 257];
 
     for rotation in n_start..n_end {
-        occurences[get_char(content, list_rotation[rotation], n_col)] += 1
+        occurences[get_char(content, list_rotation[rotation], n_col) as usize] += 1
     }
 
     let mut begin_idx: [usize; 257] = [n_start; 257];
@@ -36,7 +41,7 @@ fn counting_sort(
             continue;
         }
 
-        current_number = get_char(content, list_rotation[idx_rotation], n_col);
+        current_number = get_char(content, list_rotation[idx_rotation], n_col) as usize;
 
         if current_number == j {
             idx_rotation += 1;
@@ -46,7 +51,7 @@ fn counting_sort(
         list_rotation.swap(idx_rotation, begin_idx[current_number]);
         begin_idx[current_number] += 1;
 
-        if get_char(content, list_rotation[idx_rotation], n_col) == j {
+        if get_char(content, list_rotation[idx_rotation], n_col) as usize == j {
             idx_rotation += 1;
         }
     }
@@ -61,23 +66,18 @@ fn counting_sort(
     equality_list
 }
 
-fn get_element_from_rotation(content: &[usize], rotation: isize, n_col: isize) -> usize {
+fn get_element_from_rotation(content: &[u16], rotation: isize, n_col: isize) -> u16 {
     let idx = (n_col - rotation + content.len() as isize) as usize % content.len();
     content[idx]
 }
 
-fn get_element_from_suffix(content: &[usize], suffix: isize) -> usize {
-    let rotation = (content.len() as isize - suffix) % (content.len() as isize);
-    get_element_from_rotation(content, rotation, content.len() as isize - 1)
-}
-
-type GetCharFn = fn(&[usize], isize, isize) -> usize;
+type GetCharFn = fn(&[u16], isize, isize) -> u16;
 
 #[allow(dead_code)]
 type StackElement = (isize, usize, usize);
 
 #[allow(dead_code)]
-fn radix_sort(text_content: &[usize], list_rotation: &mut [isize]) {
+fn radix_sort(text_content: &[u16], list_rotation: &mut [isize]) {
     let mut stack: Vec<StackElement> = Vec::new();
 
     let equalities = counting_sort(
@@ -116,12 +116,13 @@ fn min(el1: usize, el2: usize) -> usize {
 }
 
 #[allow(dead_code)]
-pub fn old_bwt_encode(mut text_content: Vec<usize>) -> Vec<usize> {
-    let mut result: Vec<usize> = Vec::with_capacity(text_content.len());
+pub fn old_bwt_encode(text_content: &[u8]) -> Vec<u16> {
+    let mut result: Vec<u16> = Vec::with_capacity(text_content.len() + 1);
+    let mut text_content: Vec<u16> = text_content.iter().map(|&el| el as u16).collect();
 
     while !text_content.is_empty() {
         let drain_range = ..min(text_content.len(), 500_000);
-        let mut subtext: Vec<usize> = text_content.drain(drain_range).collect();
+        let mut subtext: Vec<u16> = text_content.drain(drain_range).collect();
         subtext.push(BWT_MARKER);
         let rotation_range = 0..subtext.len() as isize;
         let mut list_rotation: Vec<isize> = (rotation_range).collect();
@@ -137,97 +138,150 @@ pub fn old_bwt_encode(mut text_content: Vec<usize>) -> Vec<usize> {
     result
 }
 
-fn find_nth(
-    text_content: &[usize],
-    el: usize,
-    occur: usize,
-    last_found: &mut [Vec<usize>],
-) -> usize {
-    if last_found[256].is_empty() {
-        for (idx, &text_char) in text_content.iter().enumerate() {
-            last_found[text_char].push(idx);
-        }
+/// Inverse BWT of a single block, last_column must contain exactly one BWT_MARKER
+pub fn bwt_decode(last_column: &[u16]) -> Result<Vec<u8>, DlzipError> {
+    if last_column.iter().any(|&el| el > BWT_MARKER) {
+        return Err(DlzipError::CorruptData);
     }
-    last_found[el][occur - 1]
+    let mut markers = (0..last_column.len()).filter(|&idx| last_column[idx] == BWT_MARKER);
+    let (Some(primary_index), None) = (markers.next(), markers.next()) else {
+        return Err(DlzipError::MissingBwtMarker);
+    };
+
+    let last_column: Vec<u8> = last_column
+        .iter()
+        .filter(|&&el| el != BWT_MARKER)
+        .map(|&el| el as u8)
+        .collect();
+    bwt_decode_block(&last_column, primary_index)
 }
 
-fn find_rank(text_content: &[usize], idx: usize) -> usize {
-    let mut begin: usize = 0;
-    let mut end = idx;
-    let mut m: usize;
-    let el = text_content[end];
-
-    while end > begin {
-        m = (begin + end) / 2;
-        if text_content[m] < el {
-            begin = m + 1;
-        } else if text_content[m] >= el {
-            end = m;
+/// BWT of a single block, the output contains the BWT_MARKER
+pub fn bwt_encode(text_content: &[u8]) -> Vec<u16> {
+    let (last_column, primary_index) = bwt_encode_block(text_content);
+    let mut result: Vec<u16> = Vec::with_capacity(last_column.len() + 1);
+    result.extend(last_column[..primary_index].iter().map(|&el| el as u16));
+    result.push(BWT_MARKER);
+    result.extend(last_column[primary_index..].iter().map(|&el| el as u16));
+
+    result
+}
+
+/// BWT of a single block, the marker is removed from the output and
+/// its position (the primary index) is returned alongside
+pub fn bwt_encode_block(block: &[u8]) -> (Vec<u8>, usize) {
+    let mut last_column: Vec<u8> = Vec::with_capacity(block.len());
+    let mut primary_index = 0;
+
+    // sais reserves 0 for its sentinel, so every symbol is shifted by one
+    let mut sub_text: Vec<u16> = block.iter().map(|&el| el as u16 + 1).collect();
+    sub_text.push(BWT_MARKER + 1);
+    let suffix_array = sais(&mut sub_text, BWT_MARKER as usize + 2);
+
+    // The last column holds the symbol before each suffix, the one before
+    // the whole block is the marker
+    for &suffix in suffix_array[1..].iter() {
+        if suffix == 0 {
+            primary_index = last_column.len();
+        } else {
+            last_column.push(block[suffix as usize - 1]);
         }
     }
 
-    idx - end + 1
+    (last_column, primary_index)
 }
 
-pub fn bwt_decode(mut text_content: Vec<usize>) -> Vec<u8> {
-    let mut result: Vec<u8> = Vec::with_capacity(text_content.len());
-
-    while !text_content.is_empty() {
-        let drain_range = ..min(text_content.len(), 500_001);
-        let last_column: Vec<usize> = text_content.drain(drain_range).collect();
-        let mut first_column = last_column.clone();
-        first_column.sort();
-
-        let mut last_found: Vec<Vec<usize>> = Vec::with_capacity(257);
-        for _ in 0..257 {
-            last_found.push(Vec::new());
-        }
+/// Inverse of bwt_encode_block, primary_index must be at most last_column.len()
+pub fn bwt_decode_block(last_column: &[u8], primary_index: usize) -> Result<Vec<u8>, DlzipError> {
+    if primary_index > last_column.len() {
+        return Err(DlzipError::MissingBwtMarker);
+    }
+    let n_rows = last_column.len() + 1;
 
-        let mut row = find_nth(&last_column, BWT_MARKER, 1, &mut last_found);
-        let mut el = first_column[row];
-        let mut occur: usize;
+    // Row of the first column where each byte starts, the marker is the last row
+    let mut first_row = [0u32; 256];
+    for &byte in last_column.iter() {
+        first_row[byte as usize] += 1;
+    }
+    let mut sum = 0;
+    for count in first_row.iter_mut() {
+        let tmp = *count;
+        *count = sum;
+        sum += tmp;
+    }
 
-        while el != BWT_MARKER {
-            result.push(el as u8);
-            occur = find_rank(&first_column, row);
-            row = find_nth(&last_column, el, occur, &mut last_found);
-            el = first_column[row];
+    // next_row[row of a rotation] = row of the rotation starting one symbol
+    // later, rows count the marker at primary_index in the last column
+    let mut next_row: Vec<u32> = vec![0; n_rows];
+    for (idx, &byte) in last_column.iter().enumerate() {
+        let row = if idx < primary_index { idx } else { idx + 1 };
+        next_row[first_row[byte as usize] as usize] = row as u32;
+        first_row[byte as usize] += 1;
+    }
+    next_row[n_rows - 1] = primary_index as u32;
+
+    // The block starts on the row ending with the marker, reaching it again
+    // before the end means primary_index is wrong
+    let mut result: Vec<u8> = Vec::with_capacity(last_column.len());
+    let mut row = primary_index;
+    for _ in 0..last_column.len() {
+        row = next_row[row] as usize;
+        match row.cmp(&primary_index) {
+            Ordering::Less => result.push(last_column[row]),
+            Ordering::Greater => result.push(last_column[row - 1]),
+            Ordering::Equal => break,
         }
     }
 
-    result
+    Ok(result)
 }
 
-pub fn bwt_encode(mut text_content: Vec<usize>) -> Vec<usize> {
-    let mut result: Vec<usize> = Vec::with_capacity(text_content.len());
-
-    while !text_content.is_empty() {
-        let max_idx = min(MAX_VEC_SIZE, text_content.len());
-        let mut sub_text: Vec<usize> = text_content.drain(..max_idx).collect();
-        sub_text.push(BWT_MARKER);
-        let suffix_array = sais_u8(&mut sub_text, 257);
-
-        for &suffix in suffix_array[1..].iter() {
-            result.push(get_element_from_suffix(&sub_text, suffix));
+/// BWT on the cyclic rotations of block, as bzip2 does, so no marker is needed.
+/// Return the last column and the row of the original block (bzip2 origPtr)
+pub fn bwt_encode_rotations(block: &[u8]) -> (Vec<u8>, usize) {
+    let block_len = block.len();
+    let mut last_column: Vec<u8> = Vec::with_capacity(block_len);
+    let mut orig_ptr = 0;
+
+    // Rotations are sorted through the suffixes of the doubled block, every
+    // symbol is shifted by one since sais reserves 0 for its sentinel
+    let mut doubled: Vec<u16> = block
+        .iter()
+        .chain(block.iter())
+        .map(|&el| el as u16 + 1)
+        .collect();
+    let suffix_array = sais(&mut doubled, 257);
+
+    for &suffix in suffix_array.iter() {
+        let suffix = suffix as usize;
+        if suffix < block_len {
+            if suffix == 0 {
+                orig_ptr = last_column.len();
+            }
+            last_column.push(block[(suffix + block_len - 1) % block_len]);
         }
     }
 
-    result
+    (last_column, orig_ptr)
 }
 
 #[cfg(test)]
 mod test {
-    use super::super::content;
-    use super::{bwt_decode, bwt_encode, old_bwt_encode, sais_u8};
+    use super::{
+        bwt_decode, bwt_decode_block, bwt_encode, bwt_encode_block, bwt_encode_rotations,
+        old_bwt_encode, sais, BWT_MARKER,
+    };
+    use crate::error::DlzipError;
 
     #[ignore = "broken"]
     #[test]
     fn test_sais_u8() {
-        let config = content::Config {
-            file_path: String::from("pg5097.txt"),
-        };
-        let mut text_content: Vec<usize> = content::read_content(config);
-        let list_suffix = sais_u8(&mut text_content, 257);
+        let mut text_content: Vec<u16> = std::fs::read("pg5097.txt")
+            .unwrap()
+            .iter()
+            .map(|&el| el as u16)
+            .collect();
+        let list_suffix = sais(&mut text_content, 257);
         for idx in 0..list_suffix.len() - 2 {
             let suffix = list_suffix[idx] as usize;
             let next_suffix = list_suffix[idx + 1] as usize;
@@ -241,15 +295,9 @@ mod test {
     #[test]
     fn test_new_bwt() {
         let text = String::from("mmiissiissiippii");
-        let text_content_u8 = text.as_bytes();
-        let mut text_content = Vec::new();
 
-        for &el in text_content_u8.iter() {
-            text_content.push(el as usize);
-        }
-
-        let res1 = old_bwt_encode(text_content.clone());
-        let res2 = bwt_encode(text_content);
+        let res1 = old_bwt_encode(text.as_bytes());
+        let res2 = bwt_encode(text.as_bytes());
 
         assert_eq!(res1, res2);
     }
@@ -257,16 +305,57 @@ mod test {
     #[test]
     fn test_new_bwt_decode() {
         let text = String::from("mmiissiissiippii");
-        let text_content_u8 = text.as_bytes();
-        let mut text_content = Vec::new();
 
-        for &el in text_content_u8.iter() {
-            text_content.push(el as usize);
-        }
+        let code_bwt = old_bwt_encode(text.as_bytes());
+        let decode = bwt_decode(&code_bwt).unwrap();
 
-        let code_bwt = old_bwt_encode(text_content.clone());
-        let decode = bwt_decode(code_bwt);
+        assert_eq!(decode, text.as_bytes());
+    }
+
+    #[test]
+    fn test_bwt_with_zeros() {
+        let text_content = vec![1, 2, 3, 0, 0, 0, 0, 3, 2, 1, 0, 255, 0];
 
-        assert_eq!(decode, text_content_u8);
+        let code_bwt = bwt_encode(&text_content);
+        let decode = bwt_decode(&code_bwt).unwrap();
+
+        assert_eq!(decode, text_content);
+        assert_eq!(bwt_decode(&bwt_encode(&[])).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_bwt_block() {
+        let text = "mmiissiissiippii";
+
+        let (last_column, primary_index) = bwt_encode_block(text.as_bytes());
+        assert_eq!(last_column.len(), text.len());
+
+        let decode = bwt_decode_block(&last_column, primary_index);
+        assert_eq!(decode.unwrap(), text.as_bytes());
+
+        assert_eq!(
+            bwt_decode_block(&last_column, last_column.len() + 1),
+            Err(DlzipError::MissingBwtMarker)
+        );
+        let mut last_column: Vec<u16> = last_column.iter().map(|&el| el as u16).collect();
+        assert_eq!(bwt_decode(&last_column), Err(DlzipError::MissingBwtMarker));
+        let mut two_markers = last_column.clone();
+        two_markers.insert(primary_index, BWT_MARKER);
+        two_markers.push(BWT_MARKER);
+        assert_eq!(bwt_decode(&two_markers), Err(DlzipError::MissingBwtMarker));
+        last_column.insert(primary_index, BWT_MARKER);
+        last_column[0] = 300;
+        assert_eq!(bwt_decode(&last_column), Err(DlzipError::CorruptData));
+    }
+
+    #[test]
+    fn test_bwt_rotations() {
+        let (last_column, orig_ptr) = bwt_encode_rotations(b"banana");
+        assert_eq!(last_column, b"nnbaaa");
+        assert_eq!(orig_ptr, 3);
+
+        let (last_column, orig_ptr) = bwt_encode_rotations(b"abab");
+        assert_eq!(last_column, b"bbaa");
+        assert!(orig_ptr < 2);
     }
 }
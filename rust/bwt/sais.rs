@@ -1,9 +1,32 @@
-const SENTINEL: usize = 0;
+/// Symbol of a text sorted by sais: the shifted bytes of a block, or the
+/// names of the LMS substrings in the recursive calls
+pub trait Symbol: Copy + Ord {
+    /// Smallest symbol, appended to the text
+    const SENTINEL: Self;
+
+    fn index(self) -> usize;
+}
+
+impl Symbol for u16 {
+    const SENTINEL: u16 = 0;
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Symbol for u32 {
+    const SENTINEL: u32 = 0;
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
 
 /// Build the type map and count the characters to construct bucket
 /// type_map is true for S- type and false for L- type
 ///
-fn build_type_map_u8(text: &[usize], alphabet_size: usize) -> (Vec<bool>, Vec<usize>) {
+fn build_type_map<T: Symbol>(text: &[T], alphabet_size: usize) -> (Vec<bool>, Vec<usize>) {
     let mut t: Vec<bool> = vec![false; text.len()];
     let mut char_count: Vec<usize> = vec![0; alphabet_size];
 
@@ -11,22 +34,17 @@ fn build_type_map_u8(text: &[usize], alphabet_size: usize) -> (Vec<bool>, Vec<us
     let mut j: usize;
 
     while i < text.len() - 1 {
-        char_count[text[i]] += 1;
+        char_count[text[i].index()] += 1;
         j = i + 1;
         // This case will always breaks before reaching the end bcs of SENTINEL
         while text[i] == text[j] {
-            char_count[text[j]] += 1;
+            char_count[text[j].index()] += 1;
             j += 1;
         }
 
-        if text[i] < text[j] {
-            for k in i..j {
-                t[k] = true;
-            }
-        } else {
-            for k in i..j {
-                t[k] = false;
-            }
+        let is_s_type = text[i] < text[j];
+        for t_k in t[i..j].iter_mut() {
+            *t_k = is_s_type;
         }
 
         i = j;
@@ -66,23 +84,33 @@ fn build_bucket(char_count: &[usize], alphabet_size: usize) -> (Vec<usize>, Vec<
 }
 
 /// Make a guess of suffix array sort by placing LMS characters into their buckets
-fn guess_lms_sort(text: &[usize], sa: &mut [isize], pa: &[usize], bucket_tail: &mut [usize]) {
+fn guess_lms_sort<T: Symbol>(
+    text: &[T],
+    sa: &mut [isize],
+    pa: &[usize],
+    bucket_tail: &mut [usize],
+) {
     for &el in pa.iter() {
-        bucket_tail[text[el]] -= 1;
-        let idx = bucket_tail[text[el]];
+        bucket_tail[text[el].index()] -= 1;
+        let idx = bucket_tail[text[el].index()];
         sa[idx] = el as isize;
     }
 }
 
 /// Induce sort all L- type element
-fn induce_sort_l(text: &[usize], sa: &mut [isize], bucket_head: &mut [usize], type_map: &[bool]) {
+fn induce_sort_l<T: Symbol>(
+    text: &[T],
+    sa: &mut [isize],
+    bucket_head: &mut [usize],
+    type_map: &[bool],
+) {
     let mut idx = 0;
     while idx < sa.len() {
         if sa[idx] > 0 {
             let idx_el = sa[idx] as usize - 1;
             if !type_map[idx_el] {
-                sa[bucket_head[text[idx_el]]] = sa[idx] - 1;
-                bucket_head[text[idx_el]] += 1;
+                sa[bucket_head[text[idx_el].index()]] = sa[idx] - 1;
+                bucket_head[text[idx_el].index()] += 1;
             }
         }
 
@@ -90,14 +118,19 @@ fn induce_sort_l(text: &[usize], sa: &mut [isize], bucket_head: &mut [usize], ty
     }
 }
 
-fn induce_sort_s(text: &[usize], sa: &mut [isize], bucket_tail: &mut [usize], type_map: &[bool]) {
+fn induce_sort_s<T: Symbol>(
+    text: &[T],
+    sa: &mut [isize],
+    bucket_tail: &mut [usize],
+    type_map: &[bool],
+) {
     let mut idx = sa.len() - 1;
     while idx > 0 {
         if sa[idx] > 0 {
             let idx_el = sa[idx] as usize - 1;
             if type_map[idx_el] {
-                bucket_tail[text[idx_el]] -= 1;
-                sa[bucket_tail[text[idx_el]]] = sa[idx] - 1;
+                bucket_tail[text[idx_el].index()] -= 1;
+                sa[bucket_tail[text[idx_el].index()]] = sa[idx] - 1;
             }
         }
 
@@ -109,8 +142,8 @@ fn is_lms_char(suffix: isize, type_map: &[bool]) -> bool {
     suffix > 0 && type_map[suffix as usize] && !type_map[suffix as usize - 1]
 }
 
-fn is_lms_substring_eq(
-    text: &[usize],
+fn is_lms_substring_eq<T: Symbol>(
+    text: &[T],
     type_map: &[bool],
     mut suffix1: usize,
     mut suffix2: usize,
@@ -138,18 +171,18 @@ fn is_lms_substring_eq(
     is_lms_char(suffix1 as isize, type_map) && is_lms_char(suffix2 as isize, type_map)
 }
 
-fn build_summary_string(
-    text: &[usize],
+fn build_summary_string<T: Symbol>(
+    text: &[T],
     sa: &[isize],
     pa: &[usize],
     type_map: &[bool],
-) -> (usize, Vec<usize>) {
+) -> (usize, Vec<u32>) {
     // First we scan sa to detect lms substring, when we detect one, we check if it is different from the previous
     // if not, we assign it the same name, otherwise we increase the name by 1
 
-    let mut name_map: Vec<usize> = vec![0; text.len()];
+    let mut name_map: Vec<u32> = vec![0; text.len()];
     let mut last_lms: usize = text.len() - 1;
-    let mut current_name: usize = 1;
+    let mut current_name: u32 = 1;
 
     // Insert last suffix
     name_map[text.len() - 1] = current_name;
@@ -166,30 +199,30 @@ fn build_summary_string(
         }
     }
 
-    let mut s1: Vec<usize> = Vec::with_capacity(pa.len());
+    let mut s1: Vec<u32> = Vec::with_capacity(pa.len());
     for &lms_suffix in pa.iter() {
         s1.push(name_map[lms_suffix]);
     }
 
-    (current_name, s1)
+    (current_name as usize, s1)
 }
 
-fn build_summary_sa(s1: &mut Vec<usize>, alphabet_size: usize) -> Vec<isize> {
+fn build_summary_sa(s1: &mut Vec<u32>, alphabet_size: usize) -> Vec<isize> {
     // If every character is unique directly compute SA1
     if alphabet_size == s1.len() {
         let mut sa1: Vec<isize> = vec![-1; s1.len() + 1];
         sa1[0] = s1.len() as isize;
         for (idx, &el) in s1.iter().enumerate() {
-            sa1[el] = idx as isize;
+            sa1[el as usize] = idx as isize;
         }
         sa1
     } else {
-        sais_u8(s1, alphabet_size + 1)
+        sais(s1, alphabet_size + 1)
     }
 }
 
-fn exact_lms_sort(
-    text: &[usize],
+fn exact_lms_sort<T: Symbol>(
+    text: &[T],
     sa: &mut [isize],
     sa1: &[isize],
     pa: &[usize],
@@ -198,19 +231,21 @@ fn exact_lms_sort(
     let mut idx = sa1.len() - 1;
     while idx > 0 {
         let lms_suffix = pa[sa1[idx] as usize];
-        bucket_tail[text[lms_suffix]] -= 1;
-        sa[bucket_tail[text[lms_suffix]]] = lms_suffix as isize;
+        bucket_tail[text[lms_suffix].index()] -= 1;
+        sa[bucket_tail[text[lms_suffix].index()]] = lms_suffix as isize;
 
         idx -= 1;
     }
 }
 
-pub fn sais_u8(text: &mut Vec<usize>, alphabet_size: usize) -> Vec<isize> {
+/// Suffix array of text, whose symbols must be above T::SENTINEL and below
+/// alphabet_size. The suffix of the sentinel, the empty one, comes first
+pub fn sais<T: Symbol>(text: &mut Vec<T>, alphabet_size: usize) -> Vec<isize> {
     // First push the sentinel at the end of the text
-    text.push(SENTINEL);
+    text.push(T::SENTINEL);
 
     // Then count characters and build type map
-    let (type_map, char_count) = build_type_map_u8(text, alphabet_size);
+    let (type_map, char_count) = build_type_map(text, alphabet_size);
 
     // Find all LMS characters
     let pa = find_lms_character(&type_map);
@@ -256,9 +291,9 @@ mod sais_test {
     use super::*;
 
     #[test]
-    fn test_build_type_map_u8() {
-        let text: Vec<usize> = vec![3, 1, 2, 2, 1, 7, 5, 0];
-        let (type_map, char_count) = build_type_map_u8(&text, 257);
+    fn test_build_type_map() {
+        let text: Vec<u16> = vec![3, 1, 2, 2, 1, 7, 5, 0];
+        let (type_map, char_count) = build_type_map(&text, 257);
 
         assert_eq!(
             type_map,
@@ -274,8 +309,8 @@ mod sais_test {
 
     #[test]
     fn test_build_bucket() {
-        let text: Vec<usize> = vec![3, 1, 2, 2, 1, 7, 5, 0];
-        let (_type_map, char_count) = build_type_map_u8(&text, 257);
+        let text: Vec<u16> = vec![3, 1, 2, 2, 1, 7, 5, 0];
+        let (_type_map, char_count) = build_type_map(&text, 257);
         let (bucket_head, bucket_tail) = build_bucket(&char_count, 257);
 
         assert_eq!(bucket_head[0], 0);
@@ -290,8 +325,8 @@ mod sais_test {
 
     #[test]
     fn test_guess_lms_sort() {
-        let text: Vec<usize> = vec![3, 1, 2, 2, 1, 7, 5, 0];
-        let (type_map, char_count) = build_type_map_u8(&text, 257);
+        let text: Vec<u16> = vec![3, 1, 2, 2, 1, 7, 5, 0];
+        let (type_map, char_count) = build_type_map(&text, 257);
         let (_, mut bucket_tail) = build_bucket(&char_count, 257);
         let pa = find_lms_character(&type_map);
 
@@ -303,8 +338,8 @@ mod sais_test {
 
     #[test]
     fn test_find_lms_char() {
-        let text: Vec<usize> = vec![3, 1, 2, 2, 1, 7, 5, 0];
-        let (type_map, _) = build_type_map_u8(&text, 257);
+        let text: Vec<u16> = vec![3, 1, 2, 2, 1, 7, 5, 0];
+        let (type_map, _) = build_type_map(&text, 257);
         let pa = find_lms_character(&type_map);
 
         assert_eq!(pa, vec![1, 4, 7]);
@@ -312,8 +347,8 @@ mod sais_test {
 
     #[test]
     fn test_induce_sort_l() {
-        let text: Vec<usize> = vec![2, 1, 1, 2, 1, 1, 2, 1, 3, 0];
-        let (type_map, char_count) = build_type_map_u8(&text, 257);
+        let text: Vec<u16> = vec![2, 1, 1, 2, 1, 1, 2, 1, 3, 0];
+        let (type_map, char_count) = build_type_map(&text, 257);
         let (mut bucket_head, mut bucket_tail) = build_bucket(&char_count, 257);
         let pa = find_lms_character(&type_map);
 
@@ -326,8 +361,8 @@ mod sais_test {
 
     #[test]
     fn test_induce_sort_s() {
-        let text: Vec<usize> = vec![2, 1, 1, 2, 1, 1, 2, 1, 3, 0];
-        let (type_map, char_count) = build_type_map_u8(&text, 257);
+        let text: Vec<u16> = vec![2, 1, 1, 2, 1, 1, 2, 1, 3, 0];
+        let (type_map, char_count) = build_type_map(&text, 257);
         let (mut bucket_head, mut bucket_tail) = build_bucket(&char_count, 257);
         let pa = find_lms_character(&type_map);
 
@@ -342,8 +377,8 @@ mod sais_test {
 
     #[test]
     fn test_build_summary() {
-        let text: Vec<usize> = vec![2, 1, 1, 2, 1, 1, 2, 1, 3, 0];
-        let (type_map, char_count) = build_type_map_u8(&text, 257);
+        let text: Vec<u16> = vec![2, 1, 1, 2, 1, 1, 2, 1, 3, 0];
+        let (type_map, char_count) = build_type_map(&text, 257);
         let (mut bucket_head, mut bucket_tail) = build_bucket(&char_count, 257);
         let pa = find_lms_character(&type_map);
 
@@ -360,8 +395,8 @@ mod sais_test {
 
     #[test]
     fn build_sa1_1() {
-        let text: Vec<usize> = vec![3, 1, 2, 2, 1, 7, 5, 0];
-        let (type_map, char_count) = build_type_map_u8(&text, 257);
+        let text: Vec<u16> = vec![3, 1, 2, 2, 1, 7, 5, 0];
+        let (type_map, char_count) = build_type_map(&text, 257);
         let (mut bucket_head, mut bucket_tail) = build_bucket(&char_count, 257);
         let pa = find_lms_character(&type_map);
 
@@ -378,8 +413,8 @@ mod sais_test {
 
     #[test]
     fn test_exact_sort_lms_easy() {
-        let text: Vec<usize> = vec![3, 1, 2, 2, 1, 7, 5, 0];
-        let (type_map, char_count) = build_type_map_u8(&text, 257);
+        let text: Vec<u16> = vec![3, 1, 2, 2, 1, 7, 5, 0];
+        let (type_map, char_count) = build_type_map(&text, 257);
         let (mut bucket_head, mut bucket_tail) = build_bucket(&char_count, 257);
         let pa = find_lms_character(&type_map);
 
@@ -399,16 +434,16 @@ mod sais_test {
     }
 
     #[test]
-    fn test_sais_u8_easy() {
-        let mut text: Vec<usize> = vec![3, 1, 2, 2, 1, 7, 5];
-        let sa = sais_u8(&mut text, 257);
+    fn test_sais_easy() {
+        let mut text: Vec<u16> = vec![3, 1, 2, 2, 1, 7, 5];
+        let sa = sais(&mut text, 257);
         assert_eq!(vec![7, 1, 4, 3, 2, 0, 6, 5], sa)
     }
 
     #[test]
-    fn test_sais_u8_hard() {
-        let mut text: Vec<usize> = vec![2, 1, 1, 2, 1, 1, 2, 1, 3];
-        let sa = sais_u8(&mut text, 257);
+    fn test_sais_hard() {
+        let mut text: Vec<u16> = vec![2, 1, 1, 2, 1, 1, 2, 1, 3];
+        let sa = sais(&mut text, 257);
         assert_eq!(vec![9, 1, 4, 2, 5, 7, 0, 3, 6, 8], sa);
     }
 }
@@ -0,0 +1,508 @@
+//! Reader and writer for genuine bzip2 (.bz2) streams
+//!
+//! The stages are the same as the .dlz2 pipeline, but laid out as bzip2 does:
+//! run-length encoding of the input, BWT on cyclic rotations, MTF on the used
+//! bytes only, RUNA/RUNB coding of zero runs (least significant digit first)
+//! and Huffman tables switched every 50 symbols, all bit-packed.
+
+use crate::bitio::{BitReader, BitWriter};
+use crate::bwt;
+use crate::crc;
+use crate::error::{DlzipError, OptionsError};
+use crate::huffman::{
+    canonical_codes, multi_table_lengths, read_code_lengths, read_selectors, table_count,
+    write_code_lengths, write_selectors, CanonicalDecoder, GROUP_SIZE, MAX_TABLES, MIN_TABLES,
+};
+
+const STREAM_MAGIC: &[u8; 3] = b"BZh";
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+const END_MAGIC: u64 = 0x1772_4538_5090;
+
+const RUNA: u16 = 0;
+const RUNB: u16 = 1;
+
+const MAX_SELECTORS: usize = 18002;
+
+/// Longest code written by the encoder, as bzip2
+const MAX_ENCODE_LEN: u8 = 17;
+
+pub const DEFAULT_LEVEL: u8 = 9;
+
+/// Check the "BZh" signature followed by the block size level
+pub fn is_bzip2(content: &[u8]) -> bool {
+    content.len() >= 4 && content[..3] == *STREAM_MAGIC && (b'1'..=b'9').contains(&content[3])
+}
+
+fn write_magic(magic: u64, writer: &mut BitWriter) {
+    writer.write_bits(24, (magic >> 24) as u32);
+    writer.write_bits(24, (magic & 0xff_ffff) as u32);
+}
+
+fn read_magic(reader: &mut BitReader) -> Result<u64, DlzipError> {
+    let high = reader.read_bits(24)? as u64;
+    let low = reader.read_bits(24)? as u64;
+    Ok((high << 24) | low)
+}
+
+/// Compress content into a bzip2 stream, level (1 to 9) sets the block size
+/// to level * 100_000 bytes
+pub fn compress(content: &[u8], level: u8) -> Result<Vec<u8>, OptionsError> {
+    if !(1..=9).contains(&level) {
+        return Err(OptionsError::Level(level));
+    }
+
+    // A run adds at most 5 bytes, this keeps blocks under level * 100_000
+    let max_block = 100_000 * level as usize - 19;
+
+    let mut writer = BitWriter::new();
+    for &byte in STREAM_MAGIC.iter() {
+        writer.write_bits(8, byte as u32);
+    }
+    writer.write_bits(8, (b'0' + level) as u32);
+
+    let mut stream_crc = 0;
+    let mut block: Vec<u8> = Vec::with_capacity(max_block + 5);
+    let mut block_crc = crc::Crc32::new();
+
+    // Initial run-length encoding: runs of 4 to 255 identical bytes are
+    // written as 4 bytes followed by the number of remaining repetitions
+    let mut idx = 0;
+    while idx < content.len() {
+        let byte = content[idx];
+        let mut run_end = idx + 1;
+        while run_end < content.len() && content[run_end] == byte && run_end - idx < 255 {
+            run_end += 1;
+        }
+
+        let run_len = run_end - idx;
+        block_crc.update(&content[idx..run_end]);
+        if run_len < 4 {
+            block.extend_from_slice(&content[idx..run_end]);
+        } else {
+            block.extend_from_slice(&[byte; 4]);
+            block.push((run_len - 4) as u8);
+        }
+        idx = run_end;
+
+        if block.len() >= max_block {
+            let crc = block_crc.finish();
+            write_block(&block, crc, &mut writer);
+            stream_crc = crc::combine_crc(stream_crc, crc);
+            block.clear();
+            block_crc = crc::Crc32::new();
+        }
+    }
+
+    if !block.is_empty() {
+        let crc = block_crc.finish();
+        write_block(&block, crc, &mut writer);
+        stream_crc = crc::combine_crc(stream_crc, crc);
+    }
+
+    write_magic(END_MAGIC, &mut writer);
+    writer.write_bits(32, stream_crc);
+
+    Ok(writer.finish())
+}
+
+/// MTF on the rank of each byte among the used ones, then RUNA/RUNB coding
+/// of the zero runs, the stream ends with the end of block symbol
+fn mtf_values(last_column: &[u8], unseq_to_seq: &[u8; 256], n_in_use: usize) -> Vec<u16> {
+    let end_of_block = n_in_use as u16 + 1;
+    let mut result: Vec<u16> = Vec::with_capacity(last_column.len() + 1);
+    let mut list: Vec<u8> = (0..n_in_use).map(|seq| seq as u8).collect();
+    let mut zero_run: usize = 0;
+
+    fn flush_zero_run(mut zero_run: usize, result: &mut Vec<u16>) {
+        if zero_run == 0 {
+            return;
+        }
+        // Bijective base 2, least significant digit first
+        zero_run -= 1;
+        loop {
+            result.push(if zero_run & 1 == 1 { RUNB } else { RUNA });
+            if zero_run < 2 {
+                break;
+            }
+            zero_run = (zero_run - 2) / 2;
+        }
+    }
+
+    for &byte in last_column.iter() {
+        let seq = unseq_to_seq[byte as usize];
+        if list[0] == seq {
+            zero_run += 1;
+            continue;
+        }
+
+        flush_zero_run(zero_run, &mut result);
+        zero_run = 0;
+
+        let position = list.iter().position(|&el| el == seq).unwrap();
+        list.copy_within(0..position, 1);
+        list[0] = seq;
+        result.push(position as u16 + 1);
+    }
+
+    flush_zero_run(zero_run, &mut result);
+    result.push(end_of_block);
+
+    result
+}
+
+fn write_block(block: &[u8], block_crc: u32, writer: &mut BitWriter) {
+    let (last_column, orig_ptr) = bwt::bwt_encode_rotations(block);
+
+    let mut in_use = [false; 256];
+    for &byte in block.iter() {
+        in_use[byte as usize] = true;
+    }
+    let mut unseq_to_seq = [0u8; 256];
+    let mut n_in_use = 0;
+    for (byte, _) in in_use.iter().enumerate().filter(|(_, &used)| used) {
+        unseq_to_seq[byte] = n_in_use as u8;
+        n_in_use += 1;
+    }
+
+    let symbols = mtf_values(&last_column, &unseq_to_seq, n_in_use);
+    let alpha_size = n_in_use + 2;
+
+    let n_tables = table_count(symbols.len());
+    let (lengths, selectors) = multi_table_lengths(&symbols, alpha_size, n_tables, MAX_ENCODE_LEN);
+    let codes: Vec<Vec<u32>> = lengths.iter().map(|table| canonical_codes(table)).collect();
+
+    write_magic(BLOCK_MAGIC, writer);
+    writer.write_bits(32, block_crc);
+    // Randomised blocks are deprecated
+    writer.write_bit(false);
+    writer.write_bits(24, orig_ptr as u32);
+
+    // Used bytes, as a bitmap of the used ranges of 16 bytes followed by a
+    // bitmap for each used range
+    let mut used_ranges: u32 = 0;
+    for range in 0..16 {
+        if in_use[range * 16..range * 16 + 16].iter().any(|&used| used) {
+            used_ranges |= 0x8000 >> range;
+        }
+    }
+    writer.write_bits(16, used_ranges);
+    for range in 0..16 {
+        if used_ranges & (0x8000 >> range) != 0 {
+            let mut used_bytes: u32 = 0;
+            for byte in 0..16 {
+                if in_use[range * 16 + byte] {
+                    used_bytes |= 0x8000 >> byte;
+                }
+            }
+            writer.write_bits(16, used_bytes);
+        }
+    }
+
+    writer.write_bits(3, n_tables as u32);
+    writer.write_bits(15, selectors.len() as u32);
+    write_selectors(&selectors, n_tables, writer);
+    for table in lengths.iter() {
+        write_code_lengths(table, writer);
+    }
+
+    for (group, &selector) in symbols.chunks(GROUP_SIZE).zip(selectors.iter()) {
+        let table = selector as usize;
+        for &symbol in group.iter() {
+            let symbol = symbol as usize;
+            writer.write_bits(lengths[table][symbol] as u32, codes[table][symbol]);
+        }
+    }
+}
+
+/// Decompress a bzip2 file, concatenated streams are decoded one after another
+pub fn decompress(content: &[u8]) -> Result<Vec<u8>, DlzipError> {
+    if !is_bzip2(content) {
+        return Err(DlzipError::BadMagic);
+    }
+
+    let mut result: Vec<u8> = Vec::new();
+    let mut stream = content;
+    while is_bzip2(stream) {
+        stream = decompress_stream(stream, &mut result)?;
+    }
+
+    Ok(result)
+}
+
+/// Decompress a single stream into output, return the bytes following it
+fn decompress_stream<'a>(content: &'a [u8], output: &mut Vec<u8>) -> Result<&'a [u8], DlzipError> {
+    let max_block = 100_000 * (content[3] - b'0') as usize;
+    let mut reader = BitReader::new(&content[4..]);
+
+    let mut stream_crc = 0;
+    let mut block_idx = 0;
+    loop {
+        match read_magic(&mut reader)? {
+            BLOCK_MAGIC => {
+                let expected_crc = reader.read_bits(32)?;
+                let block = read_block(&mut reader, max_block)?;
+                let block_crc = crc::block_crc(&block);
+                if block_crc != expected_crc {
+                    return Err(DlzipError::ChecksumMismatch {
+                        block: Some(block_idx),
+                        expected: expected_crc,
+                        found: block_crc,
+                    });
+                }
+                stream_crc = crc::combine_crc(stream_crc, block_crc);
+                output.extend_from_slice(&block);
+                block_idx += 1;
+            }
+            END_MAGIC => {
+                let expected_crc = reader.read_bits(32)?;
+                if stream_crc != expected_crc {
+                    return Err(DlzipError::ChecksumMismatch {
+                        block: None,
+                        expected: expected_crc,
+                        found: stream_crc,
+                    });
+                }
+                reader.align_to_byte();
+                return Ok(reader.remaining_bytes());
+            }
+            _ => return Err(DlzipError::CorruptBlockHeader),
+        }
+    }
+}
+
+/// Read a block after its magic and crc, return its original content
+fn read_block(reader: &mut BitReader, max_block: usize) -> Result<Vec<u8>, DlzipError> {
+    if reader.read_bit()? {
+        return Err(DlzipError::RandomisedBlock);
+    }
+    let orig_ptr = reader.read_bits(24)? as usize;
+
+    let mut seq_to_unseq: Vec<u8> = Vec::with_capacity(256);
+    let used_ranges = reader.read_bits(16)?;
+    for range in 0..16 {
+        if used_ranges & (0x8000 >> range) != 0 {
+            let used_bytes = reader.read_bits(16)?;
+            for byte in 0..16 {
+                if used_bytes & (0x8000 >> byte) != 0 {
+                    seq_to_unseq.push((range * 16 + byte) as u8);
+                }
+            }
+        }
+    }
+    if seq_to_unseq.is_empty() {
+        return Err(DlzipError::CorruptBlockHeader);
+    }
+    let alpha_size = seq_to_unseq.len() + 2;
+    let end_of_block = seq_to_unseq.len() as u16 + 1;
+
+    let n_groups = reader.read_bits(3)? as usize;
+    if !(MIN_TABLES..=MAX_TABLES).contains(&n_groups) {
+        return Err(DlzipError::CorruptHuffmanTable);
+    }
+    let n_selectors = reader.read_bits(15)? as usize;
+    if n_selectors == 0 {
+        return Err(DlzipError::CorruptBlockHeader);
+    }
+    let mut selectors = read_selectors(n_selectors, n_groups, reader)?;
+    // bzip2 ignores selectors past its own limit
+    selectors.truncate(MAX_SELECTORS);
+
+    let mut decoders: Vec<CanonicalDecoder> = Vec::with_capacity(n_groups);
+    for _ in 0..n_groups {
+        let lengths = read_code_lengths(alpha_size, reader)?;
+        decoders.push(CanonicalDecoder::new(&lengths)?);
+    }
+
+    // Decode the symbols, undoing the RUNA/RUNB coding and the MTF on the fly
+    let mut list: Vec<u8> = (0..seq_to_unseq.len()).map(|seq| seq as u8).collect();
+    let mut last_column: Vec<u8> = Vec::with_capacity(max_block);
+    let mut selector_idx = 0;
+    let mut group_remaining = 0;
+    let mut decoder = &decoders[0];
+    let mut zero_run: usize = 0;
+    let mut run_weight: usize = 1;
+
+    loop {
+        if group_remaining == 0 {
+            let &selector = selectors.get(selector_idx).ok_or(DlzipError::CorruptData)?;
+            decoder = &decoders[selector as usize];
+            selector_idx += 1;
+            group_remaining = GROUP_SIZE;
+        }
+        group_remaining -= 1;
+
+        let symbol = decoder.decode(reader)?;
+        if symbol == RUNA || symbol == RUNB {
+            zero_run += run_weight << symbol;
+            run_weight <<= 1;
+            if zero_run > max_block {
+                return Err(DlzipError::CorruptData);
+            }
+            continue;
+        }
+
+        if zero_run > 0 {
+            if last_column.len() + zero_run > max_block {
+                return Err(DlzipError::CorruptData);
+            }
+            let byte = seq_to_unseq[list[0] as usize];
+            last_column.resize(last_column.len() + zero_run, byte);
+            zero_run = 0;
+            run_weight = 1;
+        }
+
+        if symbol == end_of_block {
+            break;
+        }
+
+        let position = symbol as usize - 1;
+        if position >= list.len() || last_column.len() >= max_block {
+            return Err(DlzipError::CorruptData);
+        }
+        let seq = list[position];
+        list.copy_within(0..position, 1);
+        list[0] = seq;
+        last_column.push(seq_to_unseq[seq as usize]);
+    }
+
+    if orig_ptr >= last_column.len() {
+        return Err(DlzipError::CorruptBlockHeader);
+    }
+
+    let block = inverse_bwt(&last_column, orig_ptr);
+    Ok(undo_initial_rle(&block))
+}
+
+fn inverse_bwt(last_column: &[u8], orig_ptr: usize) -> Vec<u8> {
+    // Start of each byte in the first column
+    let mut first_idx = [0u32; 256];
+    for &byte in last_column.iter() {
+        first_idx[byte as usize] += 1;
+    }
+    let mut sum = 0;
+    for count in first_idx.iter_mut() {
+        let tmp = *count;
+        *count = sum;
+        sum += tmp;
+    }
+
+    // next_row[row of a rotation] = row of the rotation starting one byte later
+    let mut next_row: Vec<u32> = vec![0; last_column.len()];
+    for (row, &byte) in last_column.iter().enumerate() {
+        next_row[first_idx[byte as usize] as usize] = row as u32;
+        first_idx[byte as usize] += 1;
+    }
+
+    let mut result: Vec<u8> = Vec::with_capacity(last_column.len());
+    let mut row = next_row[orig_ptr] as usize;
+    for _ in 0..last_column.len() {
+        result.push(last_column[row]);
+        row = next_row[row] as usize;
+    }
+
+    result
+}
+
+fn undo_initial_rle(block: &[u8]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(block.len());
+    let mut run_len = 0;
+    let mut last_byte: Option<u8> = None;
+
+    let mut idx = 0;
+    while idx < block.len() {
+        let byte = block[idx];
+        idx += 1;
+
+        if run_len == 4 {
+            // byte is the number of additional repetitions of the run
+            let repeated = last_byte.unwrap();
+            result.resize(result.len() + byte as usize, repeated);
+            run_len = 0;
+            last_byte = None;
+            continue;
+        }
+
+        result.push(byte);
+        if last_byte == Some(byte) {
+            run_len += 1;
+        } else {
+            last_byte = Some(byte);
+            run_len = 1;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compress, decompress, is_bzip2, mtf_values, undo_initial_rle, DEFAULT_LEVEL};
+    use crate::error::{DlzipError, OptionsError};
+
+    // printf 'hello hello hello\n' | bzip2 -9
+    const HELLO_BZ2: [u8; 47] = [
+        0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0xe5, 0xb5, 0xf3, 0x09, 0x00,
+        0x00, 0x04, 0x51, 0x00, 0x00, 0x10, 0x40, 0x00, 0x02, 0x44, 0xa0, 0x00, 0x21, 0xb5, 0x18,
+        0x0c, 0x02, 0x90, 0x69, 0xc2, 0xa3, 0x0b, 0xb9, 0x22, 0x9c, 0x28, 0x48, 0x72, 0xda, 0xf9,
+        0x84, 0x80,
+    ];
+
+    #[test]
+    fn test_mtf_values() {
+        // "bbbaaab" with a and b used: a -> 0, b -> 1
+        let mut unseq_to_seq = [0u8; 256];
+        unseq_to_seq[b'b' as usize] = 1;
+        let symbols = mtf_values(b"bbbaaab", &unseq_to_seq, 2);
+
+        // b: MTF 1 -> 2, bb: run of 2 -> RUNB, a: MTF 1 -> 2, aa: RUNB, b: 2, EOB: 3
+        assert_eq!(symbols, vec![2, 1, 2, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_undo_initial_rle() {
+        assert_eq!(undo_initial_rle(b"abbbb\x02c"), b"abbbbbbc");
+        assert_eq!(undo_initial_rle(b"aaaa\x00aaa"), b"aaaaaaa");
+    }
+
+    #[test]
+    fn test_compress_decompress() {
+        let mut content: Vec<u8> = b"mmiissiissiippii, and a long run: ".to_vec();
+        content.extend_from_slice(&[b'z'; 1000]);
+        content.extend_from_slice(&[0; 3]);
+        content.extend(0..=255);
+
+        let code = compress(&content, DEFAULT_LEVEL).unwrap();
+        assert!(is_bzip2(&code));
+        assert_eq!(decompress(&code).unwrap(), content);
+
+        // Small blocks, and concatenated streams
+        let mut code = compress(&content.repeat(200), 1).unwrap();
+        code.extend_from_slice(&compress(b"", 1).unwrap());
+        code.extend_from_slice(&compress(b"end", 1).unwrap());
+        let mut expected = content.repeat(200);
+        expected.extend_from_slice(b"end");
+        assert_eq!(decompress(&code).unwrap(), expected);
+
+        for level in [0, 10] {
+            assert_eq!(compress(b"", level), Err(OptionsError::Level(level)));
+        }
+    }
+
+    #[test]
+    fn test_decompress_bzip2_file() {
+        assert_eq!(decompress(&HELLO_BZ2).unwrap(), b"hello hello hello\n");
+    }
+
+    #[test]
+    fn test_decompress_invalid() {
+        assert_eq!(decompress(b"DLZ2"), Err(DlzipError::BadMagic));
+        assert_eq!(decompress(&HELLO_BZ2[..30]), Err(DlzipError::Truncated));
+
+        let mut corrupted = HELLO_BZ2;
+        corrupted[44] ^= 1;
+        assert!(matches!(
+            decompress(&corrupted),
+            Err(DlzipError::ChecksumMismatch { block: None, .. })
+        ));
+    }
+}
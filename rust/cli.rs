@@ -0,0 +1,310 @@
+//! Command line of the dlzip2 binary, its flags follow bzip2
+
+use std::path::{Path, PathBuf};
+
+use dlzip2::{bzip2, Options};
+
+pub const DLZ2_SUFFIX: &str = ".dlz2";
+pub const BZ2_SUFFIX: &str = ".bz2";
+/// File path standing for the standard input
+pub const STDIN_PATH: &str = "-";
+
+pub const USAGE: &str = "\
+usage: dlzip2 [flags and input files in any order]
+
+   With no file, or -, the standard input is de/compressed to the
+   standard output
+
+   -h --help           print this message
+   -d --decompress     force decompression
+   -z --compress       force compression
+   -t --test           test compressed file integrity
+   -l --list           list the sizes, blocks, flags and checksum recorded
+                       in .dlz2 files, without decompressing them
+   -k --keep           keep (don't delete) input files
+   -f --force          overwrite existing output files
+   -c --stdout         output to standard out
+   -v --verbose        be verbose
+   -q --quiet          suppress noncritical error messages
+   -V --version        display software version
+   --bz2               compress in bzip2 format (.bz2), decompression
+                       recognises both formats
+   --multi-table       code each block with several Huffman tables
+   -T --threads N      compress N blocks in parallel, 0 for one per cpu,
+                       capped to 4 per cpu
+   -1 .. -9            block size of 100k .. 900k (default 900k), in both
+                       formats
+
+   Short flags can be combined, as in -dkv
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Compress,
+    Decompress,
+    Test,
+}
+
+/// Parsed command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub mode: Mode,
+    pub file_paths: Vec<String>,
+    pub keep: bool,
+    pub force: bool,
+    pub stdout: bool,
+    pub verbose: bool,
+    pub quiet: bool,
+    pub bz2: bool,
+    /// Block size level, 1 to 9 for blocks of 100k to 900k bytes
+    pub level: u8,
+    pub multi_table: bool,
+    pub threads: usize,
+    /// Print the usage or the version instead of processing files
+    pub help: bool,
+    pub version: bool,
+    /// Print the metadata of the files instead of processing them
+    pub list: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mode: Mode::Compress,
+            file_paths: Vec::new(),
+            keep: false,
+            force: false,
+            stdout: false,
+            verbose: false,
+            quiet: false,
+            bz2: false,
+            level: bzip2::DEFAULT_LEVEL,
+            multi_table: false,
+            threads: 1,
+            help: false,
+            version: false,
+            list: false,
+        }
+    }
+}
+
+impl Config {
+    /// Parse the arguments following the program name, return a message
+    /// describing the first invalid one
+    pub fn parse(args: &[String]) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut args = args.iter();
+        let mut only_files = false;
+
+        while let Some(arg) = args.next() {
+            if only_files || arg == STDIN_PATH || !arg.starts_with('-') {
+                config.file_paths.push(arg.clone());
+            } else if arg == "--" {
+                only_files = true;
+            } else if let Some(long) = arg.strip_prefix("--") {
+                match long {
+                    "threads" => {
+                        let count = args.next().ok_or("--threads needs a value")?;
+                        config.threads = parse_threads(count)?;
+                    }
+                    _ => config.set_long_flag(long)?,
+                }
+            } else {
+                let flags = &arg[1..];
+                for (idx, flag) in flags.char_indices() {
+                    if flag == 'T' {
+                        // The count is the rest of the argument, or the next one
+                        let rest = &flags[idx + 1..];
+                        let count = if rest.is_empty() {
+                            args.next().ok_or("-T needs a value")?
+                        } else {
+                            rest
+                        };
+                        config.threads = parse_threads(count)?;
+                        break;
+                    }
+                    config.set_short_flag(flag)?;
+                }
+            }
+        }
+        if config.file_paths.is_empty() {
+            config.file_paths.push(String::from(STDIN_PATH));
+        }
+
+        Ok(config)
+    }
+
+    /// True if some output goes to the standard output
+    pub fn writes_stdout(&self) -> bool {
+        self.stdout || self.file_paths.iter().any(|path| path == STDIN_PATH)
+    }
+
+    fn set_short_flag(&mut self, flag: char) -> Result<(), String> {
+        match flag {
+            'h' => self.help = true,
+            'd' => self.mode = Mode::Decompress,
+            'z' => self.mode = Mode::Compress,
+            't' => self.mode = Mode::Test,
+            'l' => self.list = true,
+            'k' => self.keep = true,
+            'f' => self.force = true,
+            'c' => self.stdout = true,
+            'v' => self.verbose = true,
+            'q' => self.quiet = true,
+            'V' => self.version = true,
+            '1'..='9' => self.level = flag as u8 - b'0',
+            _ => return Err(format!("invalid flag -{}", flag)),
+        }
+        Ok(())
+    }
+
+    fn set_long_flag(&mut self, flag: &str) -> Result<(), String> {
+        match flag {
+            "help" => self.help = true,
+            "decompress" => self.mode = Mode::Decompress,
+            "compress" => self.mode = Mode::Compress,
+            "test" => self.mode = Mode::Test,
+            "list" => self.list = true,
+            "keep" => self.keep = true,
+            "force" => self.force = true,
+            "stdout" => self.stdout = true,
+            "verbose" => self.verbose = true,
+            "quiet" => self.quiet = true,
+            "version" => self.version = true,
+            "bz2" => self.bz2 = true,
+            "multi-table" => self.multi_table = true,
+            _ => return Err(format!("invalid flag --{}", flag)),
+        }
+        Ok(())
+    }
+
+    /// Options of the .dlz2 compression
+    pub fn options(&self) -> Options {
+        Options {
+            block_size: self.level as usize * 100_000,
+            multi_table: self.multi_table,
+            threads: self.threads,
+            ..Options::default()
+        }
+    }
+
+    /// Path of the file written when compressing input_path, None if it
+    /// already has the suffix of the output format
+    pub fn compressed_path(&self, input_path: &Path) -> Option<PathBuf> {
+        let suffix = if self.bz2 { BZ2_SUFFIX } else { DLZ2_SUFFIX };
+        let name = input_path.as_os_str();
+        if name.to_string_lossy().ends_with(suffix) {
+            return None;
+        }
+        let mut name = name.to_os_string();
+        name.push(suffix);
+        Some(PathBuf::from(name))
+    }
+}
+
+fn parse_threads(count: &str) -> Result<usize, String> {
+    count
+        .parse()
+        .map_err(|_| format!("invalid number of threads {}", count))
+}
+
+/// Path of the file written when decompressing input_path, the .dlz2 or .bz2
+/// suffix is removed. The bool is false if there is no such suffix, ".out"
+/// is added instead
+pub fn decompressed_path(input_path: &Path) -> (PathBuf, bool) {
+    if let Some(name) = input_path.file_name().and_then(|name| name.to_str()) {
+        for suffix in [DLZ2_SUFFIX, BZ2_SUFFIX] {
+            match name.strip_suffix(suffix) {
+                Some(stem) if !stem.is_empty() => return (input_path.with_file_name(stem), true),
+                _ => (),
+            }
+        }
+    }
+    let mut path = input_path.as_os_str().to_os_string();
+    path.push(".out");
+    (PathBuf::from(path), false)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{decompressed_path, Config, Mode};
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Config::parse(&args)
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = parse(&["-dkv", "a.dlz2", "--force", "b.bz2"]).unwrap();
+        assert_eq!(config.mode, Mode::Decompress);
+        assert!(config.keep && config.verbose && config.force && !config.stdout);
+        assert_eq!(config.file_paths, vec!["a.dlz2", "b.bz2"]);
+
+        let config = parse(&["-T4", "--bz2", "-3c", "--", "-file"]).unwrap();
+        assert_eq!(config.mode, Mode::Compress);
+        assert_eq!((config.threads, config.level), (4, 3));
+        let options = config.options();
+        assert_eq!((options.block_size, options.threads), (300_000, 4));
+        assert!(!options.multi_table);
+        let options = parse(&["--multi-table"]).unwrap().options();
+        assert_eq!(options.block_size, 900_000);
+        assert!(options.multi_table);
+        assert!(config.bz2 && config.stdout);
+        assert_eq!(config.file_paths, vec!["-file"]);
+
+        let config = parse(&["-kT", "0", "--threads", "2", "-t"]).unwrap();
+        assert_eq!((config.mode, config.threads), (Mode::Test, 2));
+        assert!(config.keep);
+        assert_eq!(config.file_paths, vec!["-"]);
+        assert!(config.writes_stdout());
+        let config = parse(&["-d", "a.dlz2", "-"]).unwrap();
+        assert_eq!(config.file_paths, vec!["a.dlz2", "-"]);
+        assert!(!parse(&["a.dlz2"]).unwrap().writes_stdout());
+
+        assert!(parse(&["-l"]).unwrap().list);
+        assert!(parse(&["-d", "--list"]).unwrap().list);
+
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        assert!(parse(&["-T"]).is_err());
+        assert!(parse(&["--threads", "many"]).is_err());
+    }
+
+    #[test]
+    fn test_output_paths() {
+        let config = Config::default();
+        assert_eq!(
+            config.compressed_path(Path::new("dir/file.txt")),
+            Some(PathBuf::from("dir/file.txt.dlz2"))
+        );
+        assert_eq!(config.compressed_path(Path::new("file.dlz2")), None);
+        let config = Config {
+            bz2: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            config.compressed_path(Path::new("file.dlz2")),
+            Some(PathBuf::from("file.dlz2.bz2"))
+        );
+
+        assert_eq!(
+            decompressed_path(Path::new("dir/file.txt.dlz2")),
+            (PathBuf::from("dir/file.txt"), true)
+        );
+        assert_eq!(
+            decompressed_path(Path::new("file.bz2")),
+            (PathBuf::from("file"), true)
+        );
+        assert_eq!(
+            decompressed_path(Path::new("file.gz")),
+            (PathBuf::from("file.gz.out"), false)
+        );
+        assert_eq!(
+            decompressed_path(Path::new(".dlz2")),
+            (PathBuf::from(".dlz2.out"), false)
+        );
+    }
+}
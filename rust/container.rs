@@ -0,0 +1,594 @@
+use std::io::{self, Read};
+
+use crate::bitio::{BitReader, BitWriter};
+use crate::crc;
+use crate::error::DlzipError;
+
+/// Signature opening every .dlz2 stream
+pub const MAGIC: [u8; 4] = *b"DLZ2";
+/// Version of the layout written. Any change of the header or frames bumps
+/// it, and the older versions stay readable:
+/// - 1: 14 bytes header without block size nor crc, end frame holding only the
+///   stream crc. The blocks are framed as in version 2
+/// - 2: the header records the block size and a crc of itself, the end frame
+///   the block count and the original length
+pub const FORMAT_VERSION: u8 = 2;
+
+// Pipeline stages applied on the content, in encoding order
+pub const FLAG_BWT: u8 = 0b0000_0001;
+pub const FLAG_MTF: u8 = 0b0000_0010;
+pub const FLAG_ZRLE: u8 = 0b0000_0100;
+pub const FLAG_HUFFMAN: u8 = 0b0000_1000;
+pub const ALL_STAGES: u8 = FLAG_BWT | FLAG_MTF | FLAG_ZRLE | FLAG_HUFFMAN;
+/// The Huffman stage switches between several tables
+pub const FLAG_MULTI_TABLE: u8 = 0b0001_0000;
+const KNOWN_FLAGS: u8 = ALL_STAGES | FLAG_MULTI_TABLE;
+
+/// magic (4 bytes) | version (1 byte) | flags (1 byte) | block size (4 bytes)
+/// | original length (8 bytes) | crc of the previous bytes (4 bytes), big endian
+pub const HEADER_SIZE: usize = 22;
+/// magic | version | flags | original length, as in version 2
+pub const V1_HEADER_SIZE: usize = 14;
+/// Original length of a stream written before its whole content was known.
+/// The end frame of a version 1 stream records neither its block count nor
+/// its original length, they are read as UNKNOWN_LEN
+pub const UNKNOWN_LEN: u64 = u64::MAX;
+
+/// Size of the header of the given version
+pub fn header_size(version: u8) -> usize {
+    match version {
+        1 => V1_HEADER_SIZE,
+        _ => HEADER_SIZE,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub flags: u8,
+    /// Largest number of bytes of content in a block, u32::MAX in version 1
+    /// streams which don't record it
+    pub block_size: u32,
+    pub original_len: u64,
+}
+
+impl Header {
+    pub fn new(flags: u8, block_size: u32, original_len: u64) -> Self {
+        Header {
+            version: FORMAT_VERSION,
+            flags,
+            block_size,
+            original_len,
+        }
+    }
+
+    pub fn has_stage(&self, flag: u8) -> bool {
+        self.flags & flag == flag
+    }
+
+    pub fn write(&self, output: &mut Vec<u8>) {
+        let mut writer = BitWriter::new();
+        writer.write_bytes(&MAGIC);
+        writer.write_bits(8, self.version as u32);
+        writer.write_bits(8, self.flags as u32);
+        writer.write_bits(32, self.block_size);
+        writer.write_u64(self.original_len);
+        let mut header = writer.finish();
+        header.extend(crc::block_crc(&header).to_be_bytes());
+        debug_assert_eq!(header.len(), HEADER_SIZE);
+        output.extend(header);
+    }
+
+    /// Parse and validate the header at the beginning of content,
+    /// return it with the rest of the stream
+    pub fn read(content: &[u8]) -> Result<(Header, &[u8]), DlzipError> {
+        if content.len() < MAGIC.len() || content[..MAGIC.len()] != MAGIC {
+            return Err(DlzipError::BadMagic);
+        }
+        let mut reader = BitReader::new(&content[MAGIC.len()..]);
+
+        let version = reader.read_bits(8)? as u8;
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(DlzipError::UnsupportedVersion(version));
+        }
+
+        // The layout of the header depends on the version, check it's intact
+        // before reading its fields
+        let header_size = header_size(version);
+        if content.len() < header_size {
+            return Err(DlzipError::Truncated);
+        }
+        if version > 1 {
+            let (fields, header_crc) = content[..HEADER_SIZE].split_at(HEADER_SIZE - 4);
+            if crc::block_crc(fields).to_be_bytes() != header_crc {
+                return Err(DlzipError::CorruptHeader);
+            }
+        }
+
+        // Huffman is the only entropy coder, so a stream without it can't be read
+        let flags = reader.read_bits(8)? as u8;
+        if flags & !KNOWN_FLAGS != 0 || flags & FLAG_HUFFMAN == 0 {
+            return Err(DlzipError::UnsupportedFlags(flags));
+        }
+
+        let header = Header {
+            version,
+            flags,
+            block_size: if version > 1 {
+                reader.read_bits(32)?
+            } else {
+                u32::MAX
+            },
+            original_len: reader.read_u64()?,
+        };
+
+        Ok((header, &content[header_size..]))
+    }
+}
+
+// Tags opening each frame following the header
+pub const BLOCK_TAG: u8 = b'B';
+pub const END_TAG: u8 = b'E';
+
+/// tag (1 byte) | block length | primary index | block crc | payload length
+/// (4 bytes each, big endian)
+pub const BLOCK_HEADER_SIZE: usize = 17;
+/// tag (1 byte) | stream crc (4 bytes) | block count (8 bytes) | original
+/// length (8 bytes), big endian
+pub const END_FRAME_SIZE: usize = 21;
+/// tag | stream crc
+pub const V1_END_FRAME_SIZE: usize = 5;
+
+/// Frame of a compressed block, followed by payload_len bytes of payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    /// Number of bytes of the original content in this block
+    pub block_len: u32,
+    /// Position of the BWT marker in the last column
+    pub primary_index: u32,
+    /// CRC32 of the original content of the block
+    pub block_crc: u32,
+    pub payload_len: u32,
+}
+
+impl BlockHeader {
+    pub fn write(&self, output: &mut Vec<u8>) {
+        let mut writer = BitWriter::new();
+        writer.write_bits(8, BLOCK_TAG as u32);
+        writer.write_bits(32, self.block_len);
+        writer.write_bits(32, self.primary_index);
+        writer.write_bits(32, self.block_crc);
+        writer.write_bits(32, self.payload_len);
+        debug_assert_eq!(writer.position(), BLOCK_HEADER_SIZE * 8);
+        output.extend(writer.finish());
+    }
+}
+
+/// Frame closing the stream, it records what the header can't know before
+/// the content is compressed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamEnd {
+    /// Combined crc of all blocks
+    pub stream_crc: u32,
+    pub block_count: u64,
+    pub original_len: u64,
+}
+
+impl StreamEnd {
+    pub fn write(&self, output: &mut Vec<u8>) {
+        let mut writer = BitWriter::new();
+        writer.write_bits(8, END_TAG as u32);
+        writer.write_bits(32, self.stream_crc);
+        writer.write_u64(self.block_count);
+        writer.write_u64(self.original_len);
+        debug_assert_eq!(writer.position(), END_FRAME_SIZE * 8);
+        output.extend(writer.finish());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame<'a> {
+    Block(BlockHeader, &'a [u8]),
+    End(StreamEnd),
+}
+
+/// Fixed size part of a frame, a block header is followed by its payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameHeader {
+    Block(BlockHeader),
+    End(StreamEnd),
+}
+
+/// Size of the fixed part of the frame opened by tag, in a stream of the
+/// given version
+pub fn frame_header_size(tag: u8, version: u8) -> Result<usize, DlzipError> {
+    match tag {
+        BLOCK_TAG => Ok(BLOCK_HEADER_SIZE),
+        END_TAG if version == 1 => Ok(V1_END_FRAME_SIZE),
+        END_TAG => Ok(END_FRAME_SIZE),
+        _ => Err(DlzipError::CorruptBlockHeader),
+    }
+}
+
+/// Parse the fixed size part of the next frame of a stream of the given
+/// version, return it with the rest of the stream
+pub fn read_frame_header(content: &[u8], version: u8) -> Result<(FrameHeader, &[u8]), DlzipError> {
+    let mut reader = BitReader::new(content);
+    let tag = reader.read_bits(8)? as u8;
+    frame_header_size(tag, version)?;
+    if tag == END_TAG {
+        let stream_crc = reader.read_bits(32)?;
+        let end = if version == 1 {
+            StreamEnd {
+                stream_crc,
+                block_count: UNKNOWN_LEN,
+                original_len: UNKNOWN_LEN,
+            }
+        } else {
+            StreamEnd {
+                stream_crc,
+                block_count: reader.read_u64()?,
+                original_len: reader.read_u64()?,
+            }
+        };
+        return Ok((FrameHeader::End(end), reader.remaining_bytes()));
+    }
+
+    let header = BlockHeader {
+        block_len: reader.read_bits(32)?,
+        primary_index: reader.read_bits(32)?,
+        block_crc: reader.read_bits(32)?,
+        payload_len: reader.read_bits(32)?,
+    };
+    if header.primary_index > header.block_len {
+        return Err(DlzipError::CorruptBlockHeader);
+    }
+
+    Ok((FrameHeader::Block(header), reader.remaining_bytes()))
+}
+
+/// Parse the next frame of a stream of the given version, return it with the
+/// rest of the stream
+pub fn read_frame(content: &[u8], version: u8) -> Result<(Frame<'_>, &[u8]), DlzipError> {
+    match read_frame_header(content, version)? {
+        (FrameHeader::End(end), rest) => Ok((Frame::End(end), rest)),
+        (FrameHeader::Block(header), rest) => {
+            let payload_len = header.payload_len as usize;
+            if rest.len() < payload_len {
+                return Err(DlzipError::Truncated);
+            }
+            Ok((
+                Frame::Block(header, &rest[..payload_len]),
+                &rest[payload_len..],
+            ))
+        }
+    }
+}
+
+/// True if content, following a version 1 header, was laid out by the builds
+/// before block checksums : a Huffman stream without frames, or block frames
+/// without crc (tag, block length, primary index, payload length) closed by a
+/// lone end tag. It is only checked once the first frame can't be read, the
+/// payloads are skipped without being kept
+pub fn is_legacy_layout(mut content: impl Read) -> bool {
+    let mut tag = [0];
+    if content.read_exact(&mut tag).is_err() {
+        return false;
+    }
+    // The Huffman table opens with its 16 bits symbol count, below 512
+    if tag[0] != BLOCK_TAG && tag[0] != END_TAG {
+        return true;
+    }
+
+    while tag[0] == BLOCK_TAG {
+        let mut fields = [0; 12];
+        if content.read_exact(&mut fields).is_err() {
+            return false;
+        }
+        let payload_len = u32::from_be_bytes([fields[8], fields[9], fields[10], fields[11]]) as u64;
+        let skipped = io::copy(&mut content.by_ref().take(payload_len), &mut io::sink());
+        if skipped.ok() != Some(payload_len) || content.read_exact(&mut tag).is_err() {
+            return false;
+        }
+    }
+    tag[0] == END_TAG && content.read(&mut [0]).ok() == Some(0)
+}
+
+/// Name of each flag, in encoding order
+pub(crate) const FLAG_NAMES: [(u8, &str); 5] = [
+    (FLAG_BWT, "bwt"),
+    (FLAG_MTF, "mtf"),
+    (FLAG_ZRLE, "zrle"),
+    (FLAG_HUFFMAN, "huffman"),
+    (FLAG_MULTI_TABLE, "multi-table"),
+];
+
+/// Names of the pipeline stages and options recorded in flags, in encoding order
+pub fn flag_names(flags: u8) -> Vec<&'static str> {
+    FLAG_NAMES
+        .into_iter()
+        .filter(|&(flag, _)| flags & flag == flag)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// Metadata of a .dlz2 stream, read from its header and end frame without
+/// decoding its blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub version: u8,
+    pub flags: u8,
+    pub block_size: u32,
+    pub block_count: u64,
+    pub compressed_len: u64,
+    pub original_len: u64,
+    pub stream_crc: u32,
+}
+
+impl StreamInfo {
+    /// Read the metadata of a stream of compressed_len bytes from its first
+    /// HEADER_SIZE bytes (head) and its last END_FRAME_SIZE bytes (tail), so
+    /// large files don't need to be read entirely. Version 1 streams don't
+    /// record the block size, block count nor original length, they are
+    /// rejected as unsupported
+    pub fn read(head: &[u8], tail: &[u8], compressed_len: u64) -> Result<Self, DlzipError> {
+        let (header, _) = Header::read(head)?;
+        if header.version < FORMAT_VERSION {
+            return Err(DlzipError::UnsupportedVersion(header.version));
+        }
+        if compressed_len < (HEADER_SIZE + END_FRAME_SIZE) as u64 || tail.len() < END_FRAME_SIZE {
+            return Err(DlzipError::Truncated);
+        }
+        // A stream cut short doesn't end with an end frame
+        let end = match read_frame_header(&tail[tail.len() - END_FRAME_SIZE..], header.version) {
+            Ok((FrameHeader::End(end), _)) => end,
+            _ => return Err(DlzipError::Truncated),
+        };
+        if header.original_len != UNKNOWN_LEN && header.original_len != end.original_len {
+            return Err(DlzipError::LengthMismatch {
+                expected: header.original_len,
+                found: end.original_len,
+            });
+        }
+
+        Ok(StreamInfo {
+            version: header.version,
+            flags: header.flags,
+            block_size: header.block_size,
+            block_count: end.block_count,
+            compressed_len,
+            original_len: end.original_len,
+            stream_crc: end.stream_crc,
+        })
+    }
+}
+
+/// First line of the table printed by --list, shared by both CLIs
+#[doc(hidden)]
+pub const LIST_HEADER: &str =
+    "  compressed  uncompressed    ratio  blocks  block size  version  flags                             crc         name";
+
+/// Line of the --list table describing the stream of file_name
+#[doc(hidden)]
+pub fn list_line(info: &StreamInfo, file_name: &str) -> String {
+    format!(
+        "{:>12}  {:>12}  {:>7}  {:>6}  {:>10}  {:>7}  {:<32}  {:#010x}  {}",
+        info.compressed_len,
+        info.original_len,
+        ratio(info.compressed_len, info.original_len),
+        info.block_count,
+        info.block_size,
+        info.version,
+        flag_names(info.flags).join(","),
+        info.stream_crc,
+        file_name
+    )
+}
+
+/// Last line of the --list table, summing the streams listed
+#[doc(hidden)]
+pub fn list_totals(infos: &[StreamInfo]) -> String {
+    let compressed_len = infos.iter().map(|info| info.compressed_len).sum();
+    let original_len = infos.iter().map(|info| info.original_len).sum();
+    format!(
+        "{:>12}  {:>12}  {:>7}  {:>6}  {:<67}(totals)",
+        compressed_len,
+        original_len,
+        ratio(compressed_len, original_len),
+        infos.iter().map(|info| info.block_count).sum::<u64>(),
+        ""
+    )
+}
+
+/// Compressed size as a percentage of the original one
+fn ratio(compressed_len: u64, original_len: u64) -> String {
+    if original_len == 0 {
+        return String::from("-");
+    }
+    format!(
+        "{:.2}%",
+        100.0 * compressed_len as f64 / original_len as f64
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        flag_names, list_line, list_totals, read_frame, BlockHeader, DlzipError, Frame, Header,
+        StreamEnd, StreamInfo, ALL_STAGES, BLOCK_HEADER_SIZE, END_FRAME_SIZE, FLAG_BWT,
+        FLAG_MULTI_TABLE, FORMAT_VERSION, HEADER_SIZE, LIST_HEADER, UNKNOWN_LEN, V1_HEADER_SIZE,
+    };
+
+    #[test]
+    fn test_header() {
+        let header = Header::new(ALL_STAGES, 900_000, 445);
+        let mut stream = Vec::new();
+        header.write(&mut stream);
+        stream.extend_from_slice(&[1, 2, 3]);
+
+        assert_eq!(stream.len(), HEADER_SIZE + 3);
+
+        let (read_header, rest) = Header::read(&stream).unwrap();
+        assert_eq!(read_header, header);
+        assert_eq!(rest, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_header_invalid() {
+        let mut stream = Vec::new();
+        Header::new(ALL_STAGES, 900_000, 445).write(&mut stream);
+
+        assert_eq!(Header::read(b"BZh91AY&SY"), Err(DlzipError::BadMagic));
+        assert_eq!(Header::read(&stream[..8]), Err(DlzipError::Truncated));
+
+        let mut newer = stream.clone();
+        newer[4] = 42;
+        assert_eq!(
+            Header::read(&newer),
+            Err(DlzipError::UnsupportedVersion(42))
+        );
+        newer[4] = 0;
+        assert_eq!(Header::read(&newer), Err(DlzipError::UnsupportedVersion(0)));
+
+        for idx in 5..HEADER_SIZE {
+            let mut corrupt = stream.clone();
+            corrupt[idx] ^= 0x40;
+            assert_eq!(Header::read(&corrupt), Err(DlzipError::CorruptHeader));
+        }
+
+        let mut no_huffman = Vec::new();
+        Header::new(FLAG_BWT, 900_000, 445).write(&mut no_huffman);
+        assert_eq!(
+            Header::read(&no_huffman),
+            Err(DlzipError::UnsupportedFlags(FLAG_BWT))
+        );
+    }
+
+    #[test]
+    fn test_header_v1() {
+        // magic | version 1 | flags | original length, without block size nor crc
+        let mut stream = b"DLZ2\x01\x0f\0\0\0\0\0\0\x01\xbd".to_vec();
+        assert_eq!(stream.len(), V1_HEADER_SIZE);
+        assert_eq!(Header::read(&stream[..9]), Err(DlzipError::Truncated));
+        stream.push(b'E');
+
+        let (header, rest) = Header::read(&stream).unwrap();
+        assert_eq!(
+            header,
+            Header {
+                version: 1,
+                flags: ALL_STAGES,
+                block_size: u32::MAX,
+                original_len: 445,
+            }
+        );
+        assert_eq!(rest, b"E");
+    }
+
+    #[test]
+    fn test_block_header() {
+        let block_header = BlockHeader {
+            block_len: 500_000,
+            primary_index: 1234,
+            block_crc: 0xfc89_1918,
+            payload_len: 3,
+        };
+        let mut stream = Vec::new();
+        block_header.write(&mut stream);
+        stream.extend_from_slice(&[1, 2, 3]);
+        let end = StreamEnd {
+            stream_crc: 0xdead_beef,
+            block_count: 1,
+            original_len: 500_000,
+        };
+        end.write(&mut stream);
+
+        assert_eq!(stream.len(), BLOCK_HEADER_SIZE + 3 + END_FRAME_SIZE);
+
+        let (frame, rest) = read_frame(&stream, FORMAT_VERSION).unwrap();
+        assert_eq!(frame, Frame::Block(block_header.clone(), &[1, 2, 3]));
+        assert_eq!(
+            read_frame(rest, FORMAT_VERSION),
+            Ok((Frame::End(end), &[][..]))
+        );
+
+        // Version 1 frames blocks the same way, its end frame only has the crc
+        let (frame, rest) = read_frame(&stream, 1).unwrap();
+        assert_eq!(frame, Frame::Block(block_header, &[1, 2, 3]));
+        let end = StreamEnd {
+            stream_crc: 0xdead_beef,
+            block_count: UNKNOWN_LEN,
+            original_len: UNKNOWN_LEN,
+        };
+        assert_eq!(read_frame(rest, 1), Ok((Frame::End(end), &rest[5..])));
+    }
+
+    #[test]
+    fn test_flag_names() {
+        assert_eq!(flag_names(ALL_STAGES), ["bwt", "mtf", "zrle", "huffman"]);
+        assert_eq!(
+            flag_names(FLAG_BWT | FLAG_MULTI_TABLE),
+            ["bwt", "multi-table"]
+        );
+    }
+
+    #[test]
+    fn test_block_header_invalid() {
+        let mut stream = Vec::new();
+        BlockHeader {
+            block_len: 10,
+            primary_index: 11,
+            block_crc: 0,
+            payload_len: 0,
+        }
+        .write(&mut stream);
+        assert_eq!(
+            read_frame(&stream, FORMAT_VERSION),
+            Err(DlzipError::CorruptBlockHeader)
+        );
+
+        let mut stream = Vec::new();
+        BlockHeader {
+            block_len: 10,
+            primary_index: 2,
+            block_crc: 0,
+            payload_len: 5,
+        }
+        .write(&mut stream);
+        assert_eq!(
+            read_frame(&stream, FORMAT_VERSION),
+            Err(DlzipError::Truncated)
+        );
+        assert_eq!(read_frame(&[], FORMAT_VERSION), Err(DlzipError::Truncated));
+        assert_eq!(read_frame(b"E", FORMAT_VERSION), Err(DlzipError::Truncated));
+        assert_eq!(
+            read_frame(b"X", FORMAT_VERSION),
+            Err(DlzipError::CorruptBlockHeader)
+        );
+    }
+
+    #[test]
+    fn test_list_lines() {
+        let info = StreamInfo {
+            version: 2,
+            flags: ALL_STAGES,
+            block_size: 900_000,
+            block_count: 2,
+            compressed_len: 250,
+            original_len: 1000,
+            stream_crc: 0xdead_beef,
+        };
+        let line = list_line(&info, "file.dlz2");
+        assert_eq!(
+            line,
+            "         250          1000   25.00%       2      900000        2  \
+             bwt,mtf,zrle,huffman              0xdeadbeef  file.dlz2"
+        );
+        // Columns line up with the header
+        assert_eq!(line.find("file.dlz2"), LIST_HEADER.find("name"));
+
+        let totals = list_totals(&[info.clone(), info]);
+        assert!(totals.starts_with("         500          2000   25.00%       4  "));
+        assert_eq!(totals.find("(totals)"), LIST_HEADER.find("name"));
+    }
+}
@@ -0,0 +1,87 @@
+/// CRC32 as computed by bzip2: polynomial 0x04c11db7, MSB first,
+/// initial value and final xor 0xffffffff
+const POLYNOMIAL: u32 = 0x04c1_1db7;
+
+const CRC_TABLE: [u32; 256] = build_table();
+
+const fn build_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            if crc & 0x8000_0000 != 0 {
+                crc = (crc << 1) ^ POLYNOMIAL;
+            } else {
+                crc <<= 1;
+            }
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Incremental CRC32 of a block
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32 { state: 0xffff_ffff }
+    }
+
+    pub fn update(&mut self, content: &[u8]) {
+        for &byte in content.iter() {
+            let idx = ((self.state >> 24) as u8 ^ byte) as usize;
+            self.state = (self.state << 8) ^ CRC_TABLE[idx];
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+pub fn block_crc(content: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(content);
+    crc.finish()
+}
+
+/// Fold a block CRC into the stream CRC, blocks must be combined in order
+pub fn combine_crc(stream_crc: u32, block_crc: u32) -> u32 {
+    stream_crc.rotate_left(1) ^ block_crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::{block_crc, combine_crc, Crc32};
+
+    #[test]
+    fn test_block_crc() {
+        // CRC-32/BZIP2 check value
+        assert_eq!(block_crc(b"123456789"), 0xfc89_1918);
+        assert_eq!(block_crc(b""), 0);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xfc89_1918);
+    }
+
+    #[test]
+    fn test_combine_crc() {
+        assert_eq!(combine_crc(0, 0xfc89_1918), 0xfc89_1918);
+        assert_eq!(combine_crc(0x8000_0001, 0x0000_0001), 0x0000_0002);
+    }
+}
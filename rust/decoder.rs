@@ -0,0 +1,468 @@
+use std::io::{self, Read};
+
+use crate::container::{
+    self, BlockHeader, Frame, FrameHeader, Header, StreamEnd, BLOCK_TAG, HEADER_SIZE,
+};
+use crate::error::{DlzipError, VerifyError};
+use crate::{check_end, crc, decode_block, legacy_layout_error};
+
+/// Blocks decoded so far in a stream, shared by the streaming decoders
+struct StreamState {
+    header: Header,
+    block_idx: usize,
+    stream_crc: u32,
+    decoded_len: u64,
+}
+
+impl StreamState {
+    fn new(header: Header) -> Self {
+        StreamState {
+            header,
+            block_idx: 0,
+            stream_crc: 0,
+            decoded_len: 0,
+        }
+    }
+
+    fn decode_block(
+        &mut self,
+        block_header: &BlockHeader,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, DlzipError> {
+        let block = decode_block(&self.header, self.block_idx, block_header, payload)?;
+        self.block_idx += 1;
+        self.stream_crc = crc::combine_crc(self.stream_crc, block_header.block_crc);
+        self.decoded_len += block.len() as u64;
+        Ok(block)
+    }
+
+    fn check_end(&self, end: &StreamEnd) -> Result<(), DlzipError> {
+        check_end(
+            &self.header,
+            end,
+            self.stream_crc,
+            self.block_idx as u64,
+            self.decoded_len,
+        )
+    }
+}
+
+/// Decompress a .dlz2 stream read from an inner reader, one block at a time,
+/// so at most one block of content is held in memory. bzip2 streams aren't
+/// supported, use decompress for them.
+///
+/// Corrupt or truncated streams are reported as io::ErrorKind::InvalidData
+/// errors wrapping a VerifyError, with the index of the block the error was
+/// found in
+pub struct Dlzip2Decoder<R: Read> {
+    reader: R,
+    state: StreamState,
+    /// Compressed frame of the current block
+    frame: Vec<u8>,
+    /// Decoded content of the current block
+    block: Vec<u8>,
+    /// Bytes of block already returned
+    position: usize,
+    /// The end of stream frame has been read and checked
+    finished: bool,
+}
+
+impl<R: Read> Dlzip2Decoder<R> {
+    /// Read and validate the header of the stream
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        // The size of the header depends on its version, which follows the magic
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        reader
+            .by_ref()
+            .take(container::MAGIC.len() as u64 + 1)
+            .read_to_end(&mut header)?;
+        if let Some(&version) = header.last() {
+            let rest_len = container::header_size(version).saturating_sub(header.len());
+            reader
+                .by_ref()
+                .take(rest_len as u64)
+                .read_to_end(&mut header)?;
+        }
+        let (header, _) = Header::read(&header)?;
+
+        Ok(Dlzip2Decoder {
+            reader,
+            state: StreamState::new(header),
+            frame: Vec::new(),
+            block: Vec::new(),
+            position: 0,
+            finished: false,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.state.header
+    }
+
+    /// Decode the next block, or check the end of the stream. The errors found
+    /// in a block frame record its index
+    fn next_frame(&mut self) -> io::Result<()> {
+        let mut tag = [0];
+        read_exact_or_truncated(&mut self.reader, &mut tag)?;
+        let (version, block_idx) = (self.state.header.version, self.state.block_idx);
+        let result = self.read_frame(tag[0]);
+        if result.is_err() {
+            // The frame read so far and the rest of the stream
+            let content = self.frame.as_slice().chain(&mut self.reader);
+            if let Some(error) = legacy_layout_error(version, block_idx, content) {
+                return Err(VerifyError::from(error).into());
+            }
+        }
+        if tag[0] != BLOCK_TAG {
+            return result;
+        }
+
+        result.map_err(|err| {
+            match err
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<VerifyError>())
+            {
+                Some(VerifyError { block: None, error }) => VerifyError {
+                    block: Some(block_idx),
+                    error: error.clone(),
+                }
+                .into(),
+                _ => err,
+            }
+        })
+    }
+
+    /// Read the rest of the frame opened by tag, and decode it
+    fn read_frame(&mut self, tag: u8) -> io::Result<()> {
+        let version = self.state.header.version;
+        self.frame.clear();
+        self.frame.push(tag);
+        let header_len = container::frame_header_size(tag, version)?;
+        self.frame.resize(header_len, 0);
+        read_exact_or_truncated(&mut self.reader, &mut self.frame[1..])?;
+
+        match container::read_frame_header(&self.frame, version)?.0 {
+            FrameHeader::Block(block_header) => {
+                // Read through take so that a corrupt length can't allocate more than the stream
+                let payload_len = block_header.payload_len as usize;
+                self.reader
+                    .by_ref()
+                    .take(payload_len as u64)
+                    .read_to_end(&mut self.frame)?;
+                if self.frame.len() < header_len + payload_len {
+                    return Err(DlzipError::Truncated.into());
+                }
+
+                let payload = &self.frame[header_len..];
+                self.block = self.state.decode_block(&block_header, payload)?;
+                self.position = 0;
+            }
+            FrameHeader::End(end) => {
+                self.state.check_end(&end)?;
+                self.finished = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the inner reader, positioned after the end of the stream once
+    /// all the content has been read
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Fill exactly buf from reader, a short stream is truncated
+fn read_exact_or_truncated(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<()> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => DlzipError::Truncated.into(),
+        _ => err,
+    })
+}
+
+impl<R: Read> Read for Dlzip2Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.block.len() {
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+            self.next_frame()?;
+        }
+
+        let read = buf.len().min(self.block.len() - self.position);
+        buf[..read].copy_from_slice(&self.block[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Decompress a .dlz2 stream fed by pieces, for callers that receive the
+/// compressed content instead of reading it. bzip2 streams aren't supported.
+#[derive(Default)]
+pub struct PushDecoder {
+    /// Compressed content fed and not decoded yet, from consumed
+    buffer: Vec<u8>,
+    consumed: usize,
+    /// None until the whole header has been fed
+    state: Option<StreamState>,
+    eof: bool,
+}
+
+impl PushDecoder {
+    pub fn new() -> Self {
+        PushDecoder::default()
+    }
+
+    /// Append a piece of the stream, bytes fed after the end of the stream
+    /// are kept as unused data
+    pub fn feed(&mut self, data: &[u8]) {
+        // Drop the decoded frames once they are the larger part of the buffer
+        if self.consumed > self.buffer.len() / 2 {
+            self.buffer.drain(..self.consumed);
+            self.consumed = 0;
+        }
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Decode the next block if it has been fed entirely. Return None when
+    /// more data is needed, or once the end of the stream has been checked
+    pub fn next_block(&mut self) -> Result<Option<Vec<u8>>, DlzipError> {
+        while !self.eof {
+            let pending = &self.buffer[self.consumed..];
+            let Some(state) = self.state.as_mut() else {
+                // A wrong magic is reported without waiting for a whole header
+                let magic_len = pending.len().min(container::MAGIC.len());
+                if pending[..magic_len] != container::MAGIC[..magic_len] {
+                    return Err(DlzipError::BadMagic);
+                }
+                let header_size = match pending.get(container::MAGIC.len()) {
+                    Some(&version) => container::header_size(version),
+                    None => return Ok(None),
+                };
+                if pending.len() < header_size {
+                    return Ok(None);
+                }
+                let (header, _) = Header::read(pending)?;
+                self.state = Some(StreamState::new(header));
+                self.consumed += header_size;
+                continue;
+            };
+
+            let (version, block_idx) = (state.header.version, state.block_idx);
+            let legacy_error = || legacy_layout_error(version, block_idx, pending);
+            let (frame, rest) = match container::read_frame(pending, version) {
+                Ok(frame) => frame,
+                // Legacy layouts are only recognised once fed entirely
+                Err(DlzipError::Truncated) => return legacy_error().map_or(Ok(None), Err),
+                Err(err) => return Err(legacy_error().unwrap_or(err)),
+            };
+            self.consumed += pending.len() - rest.len();
+            match frame {
+                Frame::Block(block_header, payload) => {
+                    return match state.decode_block(&block_header, payload) {
+                        Ok(block) => Ok(Some(block)),
+                        Err(err) => Err(legacy_error().unwrap_or(err)),
+                    };
+                }
+                Frame::End(end) => {
+                    state.check_end(&end)?;
+                    self.eof = true;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The end of the stream has been reached and checked
+    pub fn eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Bytes fed after the end of the stream
+    pub fn unused_data(&self) -> &[u8] {
+        if self.eof {
+            &self.buffer[self.consumed..]
+        } else {
+            &[]
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{ErrorKind, Read};
+
+    use super::{Dlzip2Decoder, PushDecoder};
+    use crate::container::END_FRAME_SIZE;
+    use crate::error::{DlzipError, VerifyError};
+    use crate::test::{V1_STREAM, V1_UNCHECKED_STREAM, V1_UNFRAMED_STREAM};
+    use crate::{compress_with, verify, Options};
+
+    #[test]
+    fn test_decoder() {
+        let text = b"Some useless text that should be decompressed by pieces. ".repeat(20);
+        let options = Options {
+            block_size: 100,
+            ..Options::default()
+        };
+        let stream = compress_with(&text, &options).unwrap();
+
+        let mut decoder = Dlzip2Decoder::new(&stream[..]).unwrap();
+        assert_eq!(decoder.header().original_len, text.len() as u64);
+        let mut result = Vec::new();
+        let mut buf = [0; 33];
+        loop {
+            let read = decoder.read(&mut buf).unwrap();
+            if read == 0 {
+                break;
+            }
+            result.extend_from_slice(&buf[..read]);
+        }
+        assert_eq!(result, text);
+        assert_eq!(decoder.into_inner(), b"");
+
+        let stream = compress_with(&[], &options).unwrap();
+        let mut result = Vec::new();
+        Dlzip2Decoder::new(&stream[..])
+            .unwrap()
+            .read_to_end(&mut result)
+            .unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_decoder_v1() {
+        let mut result = Vec::new();
+        Dlzip2Decoder::new(&V1_STREAM[..])
+            .unwrap()
+            .read_to_end(&mut result)
+            .unwrap();
+        assert_eq!(result, b"banana bandana");
+        for len in 0..V1_STREAM.len() {
+            assert_eq!(
+                decoder_error(&V1_STREAM[..len]),
+                verify(&V1_STREAM[..len]).unwrap_err()
+            );
+        }
+
+        let mut decoder = PushDecoder::new();
+        let mut result = Vec::new();
+        for byte in V1_STREAM.chunks(1) {
+            decoder.feed(byte);
+            while let Some(mut block) = decoder.next_block().unwrap() {
+                result.append(&mut block);
+            }
+        }
+        assert!(decoder.eof());
+        assert_eq!(result, b"banana bandana");
+    }
+
+    #[test]
+    fn test_decoder_legacy_v1() {
+        for stream in [&V1_UNFRAMED_STREAM[..], &V1_UNCHECKED_STREAM] {
+            assert_eq!(
+                decoder_error(stream),
+                VerifyError::from(DlzipError::LegacyLayout)
+            );
+
+            let mut decoder = PushDecoder::new();
+            decoder.feed(stream);
+            assert_eq!(decoder.next_block(), Err(DlzipError::LegacyLayout));
+        }
+        // Frames without crc are only recognised once the end tag is fed
+        let mut decoder = PushDecoder::new();
+        decoder.feed(&V1_UNCHECKED_STREAM[..V1_UNCHECKED_STREAM.len() - 1]);
+        assert_eq!(decoder.next_block(), Ok(None));
+    }
+
+    fn decoder_error(stream: &[u8]) -> VerifyError {
+        let mut result = Vec::new();
+        let error = Dlzip2Decoder::new(stream)
+            .and_then(|mut decoder| decoder.read_to_end(&mut result))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        *error
+            .into_inner()
+            .unwrap()
+            .downcast::<VerifyError>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decoder_invalid() {
+        let stream = compress_with(b"Some useless text", &Options::default()).unwrap();
+
+        assert_eq!(decoder_error(b"BZh9").error, DlzipError::BadMagic);
+        for len in [6, 20, stream.len() - 1] {
+            assert_eq!(decoder_error(&stream[..len]).error, DlzipError::Truncated);
+        }
+
+        // The stream crc follows the tag of the end frame
+        let mut corrupt = stream.clone();
+        corrupt[stream.len() - END_FRAME_SIZE + 1] ^= 1;
+        assert!(matches!(
+            decoder_error(&corrupt),
+            VerifyError {
+                block: None,
+                error: DlzipError::ChecksumMismatch { .. }
+            }
+        ));
+
+        // Errors found in a block record its index, as verify does
+        let text = b"Some useless text that should be decompressed by pieces. ".repeat(4);
+        let options = Options {
+            block_size: 100,
+            ..Options::default()
+        };
+        let stream = compress_with(&text, &options).unwrap();
+        for len in 0..stream.len() {
+            let truncated = &stream[..len];
+            assert_eq!(decoder_error(truncated), verify(truncated).unwrap_err());
+        }
+        for idx in 0..stream.len() {
+            let mut corrupt = stream.clone();
+            corrupt[idx] ^= 0x55;
+            assert_eq!(decoder_error(&corrupt), verify(&corrupt).unwrap_err());
+        }
+        let mut corrupt = stream.clone();
+        let last_payload = stream.len() - END_FRAME_SIZE - 1;
+        corrupt[last_payload] ^= 0x55;
+        assert_eq!(decoder_error(&corrupt).block, Some(2));
+    }
+
+    #[test]
+    fn test_push_decoder() {
+        let text = b"Some useless text that should be decompressed by pieces. ".repeat(20);
+        let options = Options {
+            block_size: 100,
+            ..Options::default()
+        };
+        let mut stream = compress_with(&text, &options).unwrap();
+        stream.extend_from_slice(b"trailing");
+
+        let mut decoder = PushDecoder::new();
+        let mut result = Vec::new();
+        for piece in stream.chunks(7) {
+            decoder.feed(piece);
+            while let Some(mut block) = decoder.next_block().unwrap() {
+                result.append(&mut block);
+            }
+        }
+        assert!(decoder.eof());
+        assert_eq!(result, text);
+        assert_eq!(decoder.unused_data(), b"trailing");
+
+        let mut decoder = PushDecoder::new();
+        decoder.feed(&stream[..20]);
+        assert_eq!(decoder.next_block(), Ok(None));
+        assert!(!decoder.eof());
+
+        let mut decoder = PushDecoder::new();
+        decoder.feed(b"DLZ");
+        assert_eq!(decoder.next_block(), Ok(None));
+        decoder.feed(b"3");
+        assert_eq!(decoder.next_block(), Err(DlzipError::BadMagic));
+    }
+}
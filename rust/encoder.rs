@@ -0,0 +1,228 @@
+use std::io::{self, Write};
+use std::{mem, panic, thread};
+
+use crate::container::{Header, StreamEnd, UNKNOWN_LEN};
+use crate::{crc, encode_block, Options};
+
+/// Compress the bytes written into it as a .dlz2 stream written to an inner
+/// writer. Content is buffered until a block is full, so at most one block
+/// per thread is held in memory. The stream is only complete once finish is
+/// called.
+pub struct Dlzip2Encoder<W: Write> {
+    writer: W,
+    options: Options,
+    /// Number of blocks encoded at once, each on its own thread
+    threads: usize,
+    /// Full blocks waiting for the others of their batch
+    full_blocks: Vec<Vec<u8>>,
+    /// Content of the block being filled
+    block: Vec<u8>,
+    stream_crc: u32,
+    /// Blocks and bytes of content compressed so far, including the full blocks
+    block_count: u64,
+    original_len: u64,
+    /// Frame of the last encoded block, kept to reuse its allocation
+    frame: Vec<u8>,
+}
+
+impl<W: Write> Dlzip2Encoder<W> {
+    /// Start a stream compressed with the default options
+    pub fn new(writer: W) -> io::Result<Self> {
+        Dlzip2Encoder::with_options(writer, Options::default())
+    }
+
+    /// Start a stream, fails if an option is out of its range or if the
+    /// header can't be written
+    pub fn with_options(writer: W, options: Options) -> io::Result<Self> {
+        options.check()?;
+        Dlzip2Encoder::start(writer, options, UNKNOWN_LEN)
+    }
+
+    /// Write the header of a stream of original_len bytes, options must be valid
+    pub(crate) fn start(mut writer: W, options: Options, original_len: u64) -> io::Result<Self> {
+        let mut header = Vec::new();
+        Header::new(options.flags(), options.block_size as u32, original_len).write(&mut header);
+        writer.write_all(&header)?;
+
+        Ok(Dlzip2Encoder {
+            writer,
+            threads: options.thread_count(),
+            full_blocks: Vec::new(),
+            block: Vec::with_capacity(options.block_size.min(crate::bwt::MAX_VEC_SIZE)),
+            options,
+            stream_crc: 0,
+            block_count: 0,
+            original_len: 0,
+            frame: Vec::new(),
+        })
+    }
+
+    /// The buffered block is full, encode it now or with its batch
+    fn end_block(&mut self) -> io::Result<()> {
+        self.block_count += 1;
+        self.original_len += self.block.len() as u64;
+        if self.threads == 1 {
+            self.frame.clear();
+            let block_crc = encode_block(&self.block, &self.options, &mut self.frame);
+            self.writer.write_all(&self.frame)?;
+            self.stream_crc = crc::combine_crc(self.stream_crc, block_crc);
+            self.block.clear();
+            return Ok(());
+        }
+
+        let capacity = self.block.capacity();
+        let block = mem::replace(&mut self.block, Vec::with_capacity(capacity));
+        self.full_blocks.push(block);
+        if self.full_blocks.len() == self.threads {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Encode the full blocks on a thread each, and write their frames in order
+    fn write_batch(&mut self) -> io::Result<()> {
+        let options = &self.options;
+        let frames: Vec<(Vec<u8>, u32)> = thread::scope(|scope| {
+            let workers: Vec<_> = self
+                .full_blocks
+                .iter()
+                .map(|block| {
+                    scope.spawn(move || {
+                        let mut frame = Vec::new();
+                        let block_crc = encode_block(block, options, &mut frame);
+                        (frame, block_crc)
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|err| panic::resume_unwind(err))
+                })
+                .collect()
+        });
+        self.full_blocks.clear();
+
+        for (frame, block_crc) in frames {
+            self.writer.write_all(&frame)?;
+            self.stream_crc = crc::combine_crc(self.stream_crc, block_crc);
+        }
+        Ok(())
+    }
+
+    /// Write the last blocks and the end of the stream, return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.block.is_empty() {
+            self.end_block()?;
+        }
+        if !self.full_blocks.is_empty() {
+            self.write_batch()?;
+        }
+        let mut end = Vec::new();
+        StreamEnd {
+            stream_crc: self.stream_crc,
+            block_count: self.block_count,
+            original_len: self.original_len,
+        }
+        .write(&mut end);
+        self.writer.write_all(&end)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// The inner writer, writing into it would corrupt the stream
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+impl<W: Write> Write for Dlzip2Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let taken = buf.len().min(self.options.block_size - self.block.len());
+        self.block.extend_from_slice(&buf[..taken]);
+        if self.block.len() == self.options.block_size {
+            self.end_block()?;
+        }
+        Ok(taken)
+    }
+
+    /// Flush the inner writer, the content of an incomplete block stays
+    /// buffered so that block boundaries don't depend on flushes
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{ErrorKind, Write};
+
+    use super::Dlzip2Encoder;
+    use crate::{decompress, Options};
+
+    #[test]
+    fn test_encoder() {
+        let text = b"Some useless text that should be compressed by pieces. ".repeat(20);
+        let options = Options {
+            block_size: 100,
+            ..Options::default()
+        };
+
+        let mut encoder = Dlzip2Encoder::with_options(Vec::new(), options).unwrap();
+        for piece in text.chunks(33) {
+            encoder.write_all(piece).unwrap();
+        }
+        // Full blocks are written as soon as they are complete
+        assert!(encoder.get_ref().len() > 100);
+        let stream = encoder.finish().unwrap();
+        assert_eq!(decompress(&stream).unwrap(), text);
+
+        let stream = Dlzip2Encoder::new(Vec::new()).unwrap().finish().unwrap();
+        assert_eq!(decompress(&stream).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_encoder_threads() {
+        let text = b"Some useless text that should be compressed in parallel. ".repeat(50);
+        let options = Options {
+            block_size: 200,
+            ..Options::default()
+        };
+        let stream = crate::compress_with(&text, &options).unwrap();
+
+        // 15 blocks, the last batch is incomplete with 2 or 4 threads, more
+        // than Options::max_threads() are capped
+        for threads in [0, 2, 4, Options::max_threads(), usize::MAX] {
+            let options = Options {
+                threads,
+                ..options.clone()
+            };
+            assert!(options.thread_count() <= Options::max_threads());
+            let mut encoder = Dlzip2Encoder::start(Vec::new(), options, text.len() as u64).unwrap();
+            for piece in text.chunks(77) {
+                encoder.write_all(piece).unwrap();
+            }
+            assert_eq!(encoder.finish().unwrap(), stream);
+        }
+        assert_eq!(decompress(&stream).unwrap(), text);
+    }
+
+    #[test]
+    fn test_encoder_invalid_options() {
+        let options = Options {
+            max_code_len: 3,
+            ..Options::default()
+        };
+        let error = Dlzip2Encoder::with_options(Vec::new(), options)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::io;
+
+use crate::huffman;
+
+/// Errors raised while reading a .dlz2 stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DlzipError {
+    /// The stream doesn't start with the dlzip2 signature
+    BadMagic,
+    /// The stream was written by a newer version of the format, or is a
+    /// version 1 stream given to stream_info
+    UnsupportedVersion(u8),
+    /// The header announces pipeline stages this version doesn't know
+    UnsupportedFlags(u8),
+    /// Version 1 stream written by the builds before block checksums, which
+    /// labelled their own layouts version 1 too
+    LegacyLayout,
+    /// The stream ends before the data it announces
+    Truncated,
+    /// The header doesn't match its crc
+    CorruptHeader,
+    /// A block or end frame is malformed
+    CorruptBlockHeader,
+    /// A Huffman table can't describe a prefix code
+    CorruptHuffmanTable,
+    /// The coded symbols of a block are inconsistent
+    CorruptData,
+    /// The last column of a block doesn't hold exactly one BWT end of block marker
+    MissingBwtMarker,
+    /// bzip2 block written with the deprecated randomisation
+    RandomisedBlock,
+    /// The decoded content doesn't have the length recorded in the header
+    LengthMismatch { expected: u64, found: u64 },
+    /// The stream doesn't have the number of blocks recorded in its end frame
+    BlockCountMismatch { expected: u64, found: u64 },
+    /// The crc of a decoded block, or of the whole stream when block is None,
+    /// doesn't match the one recorded in the stream
+    ChecksumMismatch {
+        block: Option<usize>,
+        expected: u32,
+        found: u32,
+    },
+}
+
+impl fmt::Display for DlzipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DlzipError::BadMagic => write!(f, "not a dlzip2 stream (bad magic bytes)"),
+            DlzipError::UnsupportedVersion(version) => {
+                write!(f, "unsupported dlzip2 format version {}", version)
+            }
+            DlzipError::UnsupportedFlags(flags) => {
+                write!(f, "unsupported pipeline flags {:#010b}", flags)
+            }
+            DlzipError::LegacyLayout => write!(
+                f,
+                "version 1 stream of a layout older than block checksums, it can't be read"
+            ),
+            DlzipError::Truncated => write!(f, "truncated dlzip2 stream"),
+            DlzipError::CorruptHeader => write!(f, "corrupt stream header"),
+            DlzipError::CorruptBlockHeader => write!(f, "corrupt block header"),
+            DlzipError::CorruptHuffmanTable => write!(f, "corrupt Huffman table"),
+            DlzipError::CorruptData => write!(f, "corrupt compressed data"),
+            DlzipError::MissingBwtMarker => write!(f, "missing or duplicated BWT marker"),
+            DlzipError::RandomisedBlock => {
+                write!(f, "randomised bzip2 blocks are not supported")
+            }
+            DlzipError::LengthMismatch { expected, found } => write!(
+                f,
+                "decoded length mismatch: expected {} bytes, found {}",
+                expected, found
+            ),
+            DlzipError::BlockCountMismatch { expected, found } => write!(
+                f,
+                "block count mismatch: the end frame records {} blocks, found {}",
+                expected, found
+            ),
+            DlzipError::ChecksumMismatch {
+                block: Some(block),
+                expected,
+                found,
+            } => write!(
+                f,
+                "checksum mismatch in block {}: expected {:#010x}, found {:#010x}",
+                block, expected, found
+            ),
+            DlzipError::ChecksumMismatch {
+                block: None,
+                expected,
+                found,
+            } => write!(
+                f,
+                "stream checksum mismatch: expected {:#010x}, found {:#010x}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DlzipError {}
+
+/// Error found by verify, with the index of the block it was found in when it
+/// comes from a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub block: Option<usize>,
+    pub error: DlzipError,
+}
+
+/// The block of a checksum mismatch is known, the others are found in the stream
+impl From<DlzipError> for VerifyError {
+    fn from(error: DlzipError) -> VerifyError {
+        let block = match error {
+            DlzipError::ChecksumMismatch { block, .. } => block,
+            _ => None,
+        };
+        VerifyError { block, error }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.block, &self.error) {
+            // Its message already names the block
+            (_, DlzipError::ChecksumMismatch { .. }) | (None, _) => write!(f, "{}", self.error),
+            (Some(block), error) => write!(f, "{} in block {}", error, block),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Compression option out of its range, found by Options::check or
+/// bzip2::compress
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionsError {
+    /// Options::block_size isn't between 1 and u32::MAX
+    BlockSize(usize),
+    /// Options::max_code_len isn't between huffman::MIN_CODE_LEN_LIMIT and
+    /// huffman::MAX_DECODE_LEN
+    MaxCodeLen(u8),
+    /// The level of bzip2::compress isn't between 1 and 9
+    Level(u8),
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionsError::BlockSize(_) => {
+                write!(f, "block_size should be between 1 and {}", u32::MAX)
+            }
+            OptionsError::MaxCodeLen(_) => write!(
+                f,
+                "max_code_len should be between {} and {}",
+                huffman::MIN_CODE_LEN_LIMIT,
+                huffman::MAX_DECODE_LEN
+            ),
+            OptionsError::Level(_) => write!(f, "level should be between 1 and 9"),
+        }
+    }
+}
+
+impl std::error::Error for OptionsError {}
+
+/// The encoders report invalid options as invalid input
+impl From<OptionsError> for io::Error {
+    fn from(err: OptionsError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+/// Errors of the streaming decoder are reported as invalid data, wrapping a
+/// VerifyError
+impl From<VerifyError> for io::Error {
+    fn from(err: VerifyError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+impl From<DlzipError> for io::Error {
+    fn from(err: DlzipError) -> io::Error {
+        VerifyError::from(err).into()
+    }
+}
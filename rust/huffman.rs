@@ -1,6 +1,24 @@
-use std::collections::{VecDeque, HashMap};
-
-const HUFFMAN_MARKER: usize = 259;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+use crate::bitio::{BitReader, BitWriter};
+use crate::error::DlzipError;
+
+const HUFFMAN_MARKER: u16 = 259;
+const ALPHABET_SIZE: usize = HUFFMAN_MARKER as usize + 1;
+
+/// Number of symbols coded with the same table when several tables are used
+pub const GROUP_SIZE: usize = 50;
+pub const MIN_TABLES: usize = 2;
+pub const MAX_TABLES: usize = 6;
+/// Passes refining the tables on the symbols they were selected for
+const REFINE_ITERATIONS: usize = 4;
+/// Longest code written by default
+pub const DEFAULT_MAX_CODE_LEN: u8 = 17;
+/// Shortest limit that still gives a code to every symbol of the alphabet
+pub const MIN_CODE_LEN_LIMIT: u8 = 9;
+/// Longest code accepted by the decoders
+pub const MAX_DECODE_LEN: u8 = 20;
 
 #[derive(Debug)]
 
@@ -24,6 +42,7 @@ impl HuffmanTreeNode {
         }
     }
 
+    #[cfg(test)]
     fn new() -> Self {
         HuffmanTreeNode {
             symbol: None,
@@ -166,10 +185,12 @@ struct Huffman {
     codes: HashMap<usize, Vec<u8>>,
 }
 
-fn count_occurence(content: &[usize], occurence: &mut [u32; 260]) {
+/// Count the symbols of content, and the marker ending it
+fn count_occurence(content: &[u16], occurence: &mut [u32; 260]) {
     for el in content.iter() {
-        occurence[*el] += 1;
+        occurence[*el as usize] += 1;
     }
+    occurence[HUFFMAN_MARKER as usize] += 1;
 }
 
 impl Huffman {
@@ -180,9 +201,7 @@ impl Huffman {
         }
     }
 
-    fn build_tree(&mut self, content: &mut Vec<usize>) {
-        content.push(HUFFMAN_MARKER);
-
+    fn build_tree(&mut self, content: &[u16]) {
         let mut occurence = [0; 260];
         count_occurence(content, &mut occurence);
 
@@ -210,28 +229,19 @@ impl Huffman {
     }
 
     fn build_codemap_rec(node: &HuffmanTreeNode, code: Vec<u8>, codemap: &mut HashMap<usize, Vec<u8>>) {
-        if node.left_child.is_some() {
+        if let Some(left_child) = node.left_child.as_ref() {
             let mut code_left = code.clone();
             code_left.push(0);
-            Huffman::build_codemap_rec(
-                node.left_child.as_ref().unwrap().as_ref(),
-                code_left,
-                codemap,
-            );
+            Huffman::build_codemap_rec(left_child, code_left, codemap);
         }
 
-        if node.right_child.is_some() {
+        if let Some(right_child) = node.right_child.as_ref() {
             let mut code_right = code.clone();
             code_right.push(1);
-            Huffman::build_codemap_rec(
-                node.right_child.as_ref().unwrap().as_ref(),
-                code_right,
-                codemap,
-            );
+            Huffman::build_codemap_rec(right_child, code_right, codemap);
         }
 
-        if node.symbol.is_some() {
-            let index = *(node.symbol.as_ref().unwrap());
+        if let Some(index) = node.symbol {
             codemap.insert(index, code);
         }
     }
@@ -246,85 +256,83 @@ impl Huffman {
         self.codes = codemap;
     }
 
-    fn encode(&mut self, content: &mut Vec<usize>) -> Vec<u8> {
-        self.build_tree(content);
-        self.build_codemap();
-        self.build_canonical_codemap();
-
-        let mut result: Vec<u8> = Vec::new();
-
-        let mut c = 0;
-        let mut tmp: u8 = 0;
-
-        for element in content.iter() {
-            for &bit in self.codes[element as &usize].iter() {
-                tmp <<= 1;
-                tmp |= bit;
-                c += 1;
-
-                if c == 8 {
-                    result.push(tmp);
-                    tmp = 0;
-                    c = 0;
-                }
-            }
+    /// Shorten the codes of the tree when some are longer than max_len,
+    /// only their lengths matter since canonical codes are built afterward
+    fn limit_code_lengths(&mut self, content: &[u16], max_len: u8) {
+        if self
+            .codes
+            .values()
+            .all(|code| code.len() <= max_len as usize)
+        {
+            return;
         }
 
-        if c > 0 {
-            tmp <<= 8 - c;
-            result.push(tmp);
-        }
-        let codes = self.canonical_diffs();
-        let mut codes = split_symbol(&codes);
-        
-        let code_len = codes.len();
-        let first_bit_len = 0xFF00 & code_len;
-        let first_bit_len = (first_bit_len >> 8) as u8;
-        let second_bit_len = code_len as u8;
-        codes.insert(0, second_bit_len);
-        codes.insert(0, first_bit_len);
+        let mut occurence = [0; 260];
+        count_occurence(content, &mut occurence);
 
-        codes.append(&mut result);
+        let symbols: Vec<usize> = (0..occurence.len())
+            .filter(|&el| occurence[el] > 0)
+            .collect();
+        let weights: Vec<u64> = symbols.iter().map(|&el| occurence[el] as u64).collect();
+        let lengths = package_merge(&weights, max_len);
 
-        codes
+        for (&symbol, &len) in symbols.iter().zip(lengths.iter()) {
+            self.codes.insert(symbol, vec![0; len as usize]);
+        }
     }
 
-    fn decode(&mut self, mut content: Vec<u8>) -> Vec<usize> {
-        let first_bit_len = content[0] as usize;
-        let second_bit_len = content[1] as usize;
+    fn encode(&mut self, content: &[u16], max_len: u8) -> Vec<u8> {
+        self.build_tree(content);
+        self.build_codemap();
+        self.limit_code_lengths(content, max_len);
+        self.build_canonical_codemap();
 
-        let codes_len = second_bit_len + (first_bit_len << 8);
-        let codes = &content[2..codes_len+2];
-        let can_codes = merge_symbol(codes);
-        self.rebuild_tree(&can_codes);
-        content.drain(0..codes_len+2);
+        let codes = self.canonical_diffs();
+        let codes = split_symbol(&codes);
 
-        let mut result = Vec::new();
+        let mut writer = BitWriter::new();
+        writer.write_bits(16, codes.len() as u32);
+        writer.write_bytes(&codes);
 
-        let mut node = self.tree.as_ref().unwrap().as_ref();
+        // Codes as integers, to write each of them at once
+        let mut int_codes: Vec<(u32, u32)> = vec![(0, 0); ALPHABET_SIZE];
+        for (&symbol, code) in self.codes.iter() {
+            let value = code.iter().fold(0, |acc, &bit| acc << 1 | bit as u32);
+            int_codes[symbol] = (code.len() as u32, value);
+        }
+        for &element in content.iter().chain([HUFFMAN_MARKER].iter()) {
+            let (len, value) = int_codes[element as usize];
+            writer.write_bits(len, value);
+        }
 
-        for mut element in content.iter().cloned() {
-            for _ in 0..8 {
-                if element & 0x80 == 0 {
-                    node = node.left_child.as_ref().unwrap().as_ref();
-                } else if element & 0x80 == 0x80 {
-                    node = node.right_child.as_ref().unwrap().as_ref();
-                }
+        writer.finish()
+    }
 
-                if node.symbol.is_some() {
-                    let symbol = node.symbol.unwrap();
-                    if symbol == HUFFMAN_MARKER {
-                        return result;
-                    }
-                    result.push(symbol);
-                    node = self.tree.as_ref().unwrap().as_ref();
-                }
+    fn decode(&self, content: &[u8]) -> Result<Vec<u16>, DlzipError> {
+        let mut reader = BitReader::new(content);
+        let codes_len = reader.read_bits(16)? as usize;
+        let codes = reader.read_bytes(codes_len)?;
+        let can_codes = merge_symbol(codes)?;
 
-                element <<= 1;
+        let mut lengths: Vec<u8> = vec![0; ALPHABET_SIZE];
+        let mut last_length = 0;
+        for pair in can_codes.chunks(2) {
+            last_length += pair[1];
+            if pair[0] >= ALPHABET_SIZE || last_length > MAX_DECODE_LEN as usize {
+                return Err(DlzipError::CorruptHuffmanTable);
             }
+            lengths[pair[0]] = last_length as u8;
         }
+        let decoder = CanonicalDecoder::new(&lengths)?;
 
-        panic!("Didn't found huffman marker");
+        let mut result = Vec::new();
+        loop {
+            let symbol = decoder.decode(&mut reader)?;
+            if symbol == HUFFMAN_MARKER {
+                return Ok(result);
+            }
+            result.push(symbol);
+        }
     }
 
     fn canonical_diffs(&self) -> Vec<usize> {
@@ -334,7 +342,8 @@ impl Huffman {
             list_length.push((symbol, code.len()))
         }
 
-        list_length.sort_by(|a, b| a.1.cmp(&b.1));
+        // Ties are broken by symbol so the order doesn't depend on the HashMap iteration
+        list_length.sort_by_key(|&(symbol, len)| (len, symbol));
 
         let mut res: Vec<usize> = Vec::with_capacity(2 * list_length.len());
         let mut last_length = 0;
@@ -373,59 +382,6 @@ impl Huffman {
             c += 1;
         } 
     }
-
-    fn rebuild_tree_rec(node: &mut Box<HuffmanTreeNode>, mut code: Vec<u8>, symbol: usize) {
-        if code.is_empty() {
-            node.symbol = Some(symbol);
-        } else {
-            let bit = code.pop().unwrap();
-
-            if bit == 0 {
-                if let Some(boxed_node) = node.left_child.as_mut() {
-                    Huffman::rebuild_tree_rec(boxed_node, code, symbol);
-                } else {
-                    let left_child = HuffmanTreeNode::new();
-                    node.left_child = Some(Box::new(left_child));
-                    Huffman::rebuild_tree_rec(node.left_child.as_mut().unwrap(), code, symbol)
-                }
-            } else if bit == 1 {
-                if let Some(boxed_node) = node.right_child.as_mut() {
-                    Huffman::rebuild_tree_rec(boxed_node, code, symbol);
-                } else {
-                    let right_child = HuffmanTreeNode::new();
-                    node.right_child = Some(Box::new(right_child));
-                    Huffman::rebuild_tree_rec(node.right_child.as_mut().unwrap(), code, symbol);
-                }
-            }
-        }
-    }
-
-    fn rebuild_tree(&mut self, codes: &[usize]) {
-        let mut root = Box::new(HuffmanTreeNode::new());
-        let mut c = 0;
-        let mut last_length = 0;
-
-        for i in 0..codes.len() {
-            if i % 2 == 1 {
-                continue
-            } else {
-                let symbol = codes[i];
-                let length_diff = codes[i+1];
-
-                if length_diff > 0 {
-                    c <<= length_diff;
-                    last_length += length_diff;
-                }
-
-                let mut code = binary_list(c, last_length);
-                code.reverse();
-                Huffman::rebuild_tree_rec(&mut root, code, symbol);
-                c += 1;
-            }
-        }
-
-        self.tree = Some(root);
-    }
 }
 
 fn binary_list(mut x: usize, len: usize) -> Vec<u8> {
@@ -464,47 +420,847 @@ fn split_symbol(codes: &[usize]) -> Vec<u8> {
     res
 }
 
-fn merge_symbol(codes: &[u8]) -> Vec<usize> {
+/// Inverse of split_symbol, fails if the last pair is cut
+fn merge_symbol(codes: &[u8]) -> Result<Vec<usize>, DlzipError> {
     let mut res: Vec<usize>  = Vec::new();
 
     let mut i = 0;
     while i < codes.len() {
         if codes[i] == 0 {
+            if i + 2 >= codes.len() {
+                return Err(DlzipError::CorruptHuffmanTable);
+            }
             let r = 256 + codes[i+1] as usize;
             res.push(r);
             res.push(codes[i+2] as usize);
             i += 3;
         } else {
+            if i + 1 >= codes.len() {
+                return Err(DlzipError::CorruptHuffmanTable);
+            }
             res.push(codes[i] as usize);
             res.push(codes[i+1] as usize);
             i += 2;
         }
     }
 
-    res
+    Ok(res)
 }
 
-pub fn huffman_encode(mut content: Vec<usize>) -> Vec<u8> {
-    let mut huffmantree = Huffman::new();
-    huffmantree.encode(&mut content)
+/// Depth of each leaf in a Huffman tree built on weights, every weight must be positive
+fn huffman_depths(weights: &[u64]) -> Vec<u8> {
+    let n = weights.len();
+    if n == 1 {
+        return vec![1];
+    }
+
+    let mut parent: Vec<usize> = vec![0; 2 * n - 1];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = weights
+        .iter()
+        .enumerate()
+        .map(|(node, &weight)| Reverse((weight, node)))
+        .collect();
+
+    let mut next_node = n;
+    while heap.len() > 1 {
+        let Reverse((weight1, node1)) = heap.pop().unwrap();
+        let Reverse((weight2, node2)) = heap.pop().unwrap();
+        parent[node1] = next_node;
+        parent[node2] = next_node;
+        heap.push(Reverse((weight1 + weight2, next_node)));
+        next_node += 1;
+    }
+
+    // Internal nodes are created after their children, so the depths can be
+    // computed from the root downward
+    let root = next_node - 1;
+    let mut depth: Vec<u8> = vec![0; 2 * n - 1];
+    for node in (0..root).rev() {
+        depth[node] = depth[parent[node]] + 1;
+    }
+    depth.truncate(n);
+
+    depth
+}
+
+#[derive(Clone, Copy)]
+enum MergeItem {
+    Leaf(usize),
+    /// Package of two consecutive items of the previous list
+    Package,
+}
+
+/// Optimal code lengths under the constraint that no code is longer than
+/// max_len, with the package-merge algorithm. Every weight must be positive
+/// and there must be at most 2^max_len symbols.
+fn package_merge(weights: &[u64], max_len: u8) -> Vec<u8> {
+    let n = weights.len();
+    assert!(
+        n <= 1 << max_len,
+        "too many symbols for codes of {} bits",
+        max_len
+    );
+    if n == 1 {
+        return vec![1];
+    }
+
+    let mut leaves: Vec<(u64, MergeItem)> = (0..n)
+        .map(|symbol| (weights[symbol], MergeItem::Leaf(symbol)))
+        .collect();
+    leaves.sort_by_key(|&(weight, _)| weight);
+
+    // lists[0] holds the items for the longest codes, each following list
+    // merges the leaves with the packages of the previous one
+    let mut lists: Vec<Vec<(u64, MergeItem)>> = vec![leaves.clone()];
+    for _ in 1..max_len {
+        let previous = lists.last().unwrap();
+        let mut packages = previous
+            .chunks_exact(2)
+            .map(|pair| (pair[0].0 + pair[1].0, MergeItem::Package))
+            .peekable();
+
+        let mut list: Vec<(u64, MergeItem)> = Vec::with_capacity(leaves.len() + previous.len() / 2);
+        let mut leaf_idx = 0;
+        while leaf_idx < leaves.len() || packages.peek().is_some() {
+            match packages.peek() {
+                Some(&(package_weight, _))
+                    if leaf_idx >= leaves.len() || package_weight < leaves[leaf_idx].0 =>
+                {
+                    list.push(packages.next().unwrap());
+                }
+                _ => {
+                    list.push(leaves[leaf_idx]);
+                    leaf_idx += 1;
+                }
+            }
+        }
+        lists.push(list);
+    }
+
+    // The 2n - 2 cheapest items of the last list are selected, a selected
+    // package selects the first items of the previous list, and each time a
+    // leaf is selected its code gets one bit longer
+    let mut lengths: Vec<u8> = vec![0; n];
+    let mut selected = 2 * n - 2;
+    for list in lists.iter().rev() {
+        let mut n_packages = 0;
+        for &(_, item) in list[..selected].iter() {
+            match item {
+                MergeItem::Leaf(symbol) => lengths[symbol] += 1,
+                MergeItem::Package => n_packages += 1,
+            }
+        }
+        selected = 2 * n_packages;
+    }
+
+    lengths
+}
+
+/// Code length of each symbol for the given frequencies, no code is longer
+/// than max_len. Symbols with a null frequency still get a code.
+pub fn code_lengths(frequencies: &[u32], max_len: u8) -> Vec<u8> {
+    let weights: Vec<u64> = frequencies.iter().map(|&freq| freq.max(1) as u64).collect();
+
+    let lengths = huffman_depths(&weights);
+    if lengths.iter().all(|&len| len <= max_len) {
+        lengths
+    } else {
+        package_merge(&weights, max_len)
+    }
+}
+
+/// Canonical code of each symbol from its code length, codes of the same
+/// length are assigned in increasing order of symbol
+pub fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut codes: Vec<u32> = vec![0; lengths.len()];
+    let max_len = lengths.iter().copied().max().unwrap_or(0);
+
+    let mut code: u32 = 0;
+    for len in 1..=max_len {
+        for (symbol, &symbol_len) in lengths.iter().enumerate() {
+            if symbol_len == len {
+                codes[symbol] = code;
+                code += 1;
+            }
+        }
+        code <<= 1;
+    }
+
+    codes
+}
+
+/// Number of tables worth using for n_symbols symbols, as bzip2 chooses it
+pub fn table_count(n_symbols: usize) -> usize {
+    match n_symbols {
+        0..=199 => 2,
+        200..=599 => 3,
+        600..=1199 => 4,
+        1200..=2399 => 5,
+        _ => MAX_TABLES,
+    }
+}
+
+/// Code lengths of n_tables tables, and the table selected for each group of
+/// GROUP_SIZE symbols.
+/// Tables start on ranges of symbols of similar total frequency, then each
+/// group selects its cheapest table and the tables are rebuilt on the groups
+/// that selected them, REFINE_ITERATIONS times.
+pub fn multi_table_lengths(
+    symbols: &[u16],
+    alpha_size: usize,
+    n_tables: usize,
+    max_len: u8,
+) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let mut frequencies: Vec<u32> = vec![0; alpha_size];
+    for &symbol in symbols.iter() {
+        frequencies[symbol as usize] += 1;
+    }
+
+    // Initial tables, each one is cheap on its own range of symbols
+    let mut lengths: Vec<Vec<u8>> = vec![vec![0; alpha_size]; n_tables];
+    let mut remaining_freq = symbols.len() as u32;
+    let mut range_start = 0;
+    for n_part in (1..=n_tables).rev() {
+        let target_freq = remaining_freq / n_part as u32;
+        let mut range_end = range_start;
+        let mut range_freq = 0;
+        while range_freq < target_freq && range_end < alpha_size {
+            range_freq += frequencies[range_end];
+            range_end += 1;
+        }
+        // Alternate ranges give back their last symbol, as bzip2 does
+        if range_end > range_start + 1
+            && n_part != n_tables
+            && n_part != 1
+            && (n_tables - n_part) % 2 == 1
+        {
+            range_end -= 1;
+            range_freq -= frequencies[range_end];
+        }
+
+        for (symbol, len) in lengths[n_part - 1].iter_mut().enumerate() {
+            *len = if (range_start..range_end).contains(&symbol) {
+                0
+            } else {
+                15
+            };
+        }
+        range_start = range_end;
+        remaining_freq -= range_freq;
+    }
+
+    let mut selectors: Vec<u8> = Vec::with_capacity(symbols.len().div_ceil(GROUP_SIZE));
+    for _ in 0..REFINE_ITERATIONS {
+        selectors.clear();
+        let mut table_frequencies: Vec<Vec<u32>> = vec![vec![0; alpha_size]; n_tables];
+
+        for group in symbols.chunks(GROUP_SIZE) {
+            let best_table = (0..n_tables)
+                .min_by_key(|&table| {
+                    group
+                        .iter()
+                        .map(|&symbol| lengths[table][symbol as usize] as u32)
+                        .sum::<u32>()
+                })
+                .unwrap();
+            selectors.push(best_table as u8);
+            for &symbol in group.iter() {
+                table_frequencies[best_table][symbol as usize] += 1;
+            }
+        }
+
+        for (table, table_freq) in table_frequencies.iter().enumerate() {
+            lengths[table] = code_lengths(table_freq, max_len);
+        }
+    }
+
+    (lengths, selectors)
 }
 
-pub fn huffman_decode(content: Vec<u8>) -> Vec<usize> {
+/// Selectors are MTF coded, then each one is written in unary
+pub fn write_selectors(selectors: &[u8], n_tables: usize, writer: &mut BitWriter) {
+    let mut table_list: Vec<u8> = (0..n_tables as u8).collect();
+    for &selector in selectors.iter() {
+        let position = table_list.iter().position(|&el| el == selector).unwrap();
+        table_list.copy_within(0..position, 1);
+        table_list[0] = selector;
+
+        for _ in 0..position {
+            writer.write_bit(true);
+        }
+        writer.write_bit(false);
+    }
+}
+
+pub fn read_selectors(
+    n_selectors: usize,
+    n_tables: usize,
+    reader: &mut BitReader,
+) -> Result<Vec<u8>, DlzipError> {
+    let mut table_list: Vec<u8> = (0..n_tables as u8).collect();
+    let mut selectors: Vec<u8> = Vec::with_capacity(n_selectors);
+    for _ in 0..n_selectors {
+        let mut position = 0;
+        while reader.read_bit()? {
+            position += 1;
+            if position >= n_tables {
+                return Err(DlzipError::CorruptHuffmanTable);
+            }
+        }
+        let selector = table_list[position];
+        table_list.copy_within(0..position, 1);
+        table_list[0] = selector;
+        selectors.push(selector);
+    }
+
+    Ok(selectors)
+}
+
+/// Code lengths are delta coded, starting from the first length on 5 bits
+pub fn write_code_lengths(lengths: &[u8], writer: &mut BitWriter) {
+    let mut current = lengths[0];
+    writer.write_bits(5, current as u32);
+
+    for &len in lengths.iter() {
+        while current < len {
+            writer.write_bits(2, 0b10);
+            current += 1;
+        }
+        while current > len {
+            writer.write_bits(2, 0b11);
+            current -= 1;
+        }
+        writer.write_bit(false);
+    }
+}
+
+pub fn read_code_lengths(alpha_size: usize, reader: &mut BitReader) -> Result<Vec<u8>, DlzipError> {
+    let mut lengths: Vec<u8> = Vec::with_capacity(alpha_size);
+    let mut current = reader.read_bits(5)?;
+    for _ in 0..alpha_size {
+        loop {
+            if !(1..=MAX_DECODE_LEN as u32).contains(&current) {
+                return Err(DlzipError::CorruptHuffmanTable);
+            }
+            if !reader.read_bit()? {
+                break;
+            }
+            if reader.read_bit()? {
+                current -= 1;
+            } else {
+                current += 1;
+            }
+        }
+        lengths.push(current as u8);
+    }
+
+    Ok(lengths)
+}
+
+/// Canonical Huffman decoder. Codes of at most LOOKUP_BITS bits are decoded
+/// with a single lookup, longer ones by comparing the peeked bits with the
+/// first code of each length (bzip2's limit/base/perm tables)
+pub struct CanonicalDecoder {
+    /// Symbol and length of the code prefixing each LOOKUP_BITS value,
+    /// packed as symbol << 8 | length, 0 when the code is longer
+    lookup: Vec<u32>,
+    /// Number of codes of each length
+    count: Vec<u32>,
+    /// First code of each length
+    first_code: Vec<u32>,
+    /// Index in symbols of the first code of each length
+    offset: Vec<u32>,
+    /// Symbols sorted by code
+    symbols: Vec<u16>,
+}
+
+/// Bits decoded by the primary table of CanonicalDecoder
+const LOOKUP_BITS: u32 = 10;
+
+impl CanonicalDecoder {
+    pub fn new(lengths: &[u8]) -> Result<Self, DlzipError> {
+        let max_len = MAX_DECODE_LEN as usize;
+        let mut count: Vec<u32> = vec![0; max_len + 1];
+        for &len in lengths.iter() {
+            if len as usize > max_len {
+                return Err(DlzipError::CorruptHuffmanTable);
+            }
+            count[len as usize] += 1;
+        }
+        // Symbols without code
+        count[0] = 0;
+
+        let mut first_code: Vec<u32> = vec![0; max_len + 1];
+        let mut offset: Vec<u32> = vec![0; max_len + 1];
+        let mut code: u32 = 0;
+        let mut index: u32 = 0;
+        for len in 1..=max_len {
+            first_code[len] = code;
+            offset[len] = index;
+            code += count[len];
+            index += count[len];
+            // More codes of this length than the tree can hold
+            if code > 1 << len {
+                return Err(DlzipError::CorruptHuffmanTable);
+            }
+            code <<= 1;
+        }
+
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[symbol as usize] > 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[symbol as usize]);
+
+        // Every value starting with a short code decodes to its symbol
+        let mut lookup: Vec<u32> = vec![0; 1 << LOOKUP_BITS];
+        for len in 1..=LOOKUP_BITS as usize {
+            for rank in 0..count[len] {
+                let symbol = symbols[(offset[len] + rank) as usize] as u32;
+                let shift = LOOKUP_BITS as usize - len;
+                let start = ((first_code[len] + rank) << shift) as usize;
+                lookup[start..start + (1 << shift)].fill(symbol << 8 | len as u32);
+            }
+        }
+
+        Ok(CanonicalDecoder {
+            lookup,
+            count,
+            first_code,
+            offset,
+            symbols,
+        })
+    }
+
+    pub fn decode(&self, reader: &mut BitReader) -> Result<u16, DlzipError> {
+        let entry = self.lookup[reader.peek_bits(LOOKUP_BITS) as usize];
+        if entry != 0 {
+            reader.consume(entry & 0xff)?;
+            return Ok((entry >> 8) as u16);
+        }
+
+        let max_len = self.count.len() as u32 - 1;
+        let bits = reader.peek_bits(max_len);
+        for len in LOOKUP_BITS + 1..=max_len {
+            let code = bits >> (max_len - len);
+            let rank = code.wrapping_sub(self.first_code[len as usize]);
+            if rank < self.count[len as usize] {
+                reader.consume(len)?;
+                return Ok(self.symbols[(self.offset[len as usize] + rank) as usize]);
+            }
+        }
+
+        Err(DlzipError::CorruptData)
+    }
+}
+
+/// Encode content with several Huffman tables, switched every GROUP_SIZE symbols
+///
+/// Layout, bit-packed: used symbols (17 bits for ranges of 16 symbols, then
+/// 16 bits per used range) | number of tables (3 bits) | number of selectors
+/// (32 bits) | MTF coded selectors | delta coded tables | coded symbols,
+/// ended by the Huffman marker
+pub fn huffman_encode_multi(content: &[u16], max_len: u8) -> Vec<u8> {
+    let mut in_use = [false; ALPHABET_SIZE];
+    in_use[HUFFMAN_MARKER as usize] = true;
+    for &symbol in content.iter() {
+        in_use[symbol as usize] = true;
+    }
+
+    // Tables only cover the used symbols, renumbered densely
+    let mut dense_symbol: Vec<u16> = vec![0; ALPHABET_SIZE];
+    let mut alpha_size = 0;
+    for (symbol, _) in in_use.iter().enumerate().filter(|(_, &used)| used) {
+        dense_symbol[symbol] = alpha_size as u16;
+        alpha_size += 1;
+    }
+
+    let mut symbols: Vec<u16> = Vec::with_capacity(content.len() + 1);
+    symbols.extend(content.iter().map(|&symbol| dense_symbol[symbol as usize]));
+    symbols.push(dense_symbol[HUFFMAN_MARKER as usize]);
+
+    let n_tables = table_count(symbols.len());
+    let (lengths, selectors) = multi_table_lengths(&symbols, alpha_size, n_tables, max_len);
+    let codes: Vec<Vec<u32>> = lengths.iter().map(|table| canonical_codes(table)).collect();
+
+    let mut writer = BitWriter::new();
+    let n_ranges = ALPHABET_SIZE.div_ceil(16);
+    let mut used_ranges: u32 = 0;
+    for range in 0..n_ranges {
+        let range_symbols = range * 16..(range * 16 + 16).min(ALPHABET_SIZE);
+        if in_use[range_symbols].iter().any(|&used| used) {
+            used_ranges |= 1 << (n_ranges - 1 - range);
+        }
+    }
+    writer.write_bits(n_ranges as u32, used_ranges);
+    for range in 0..n_ranges {
+        if used_ranges & (1 << (n_ranges - 1 - range)) != 0 {
+            let mut used_symbols: u32 = 0;
+            for symbol in 0..16 {
+                if in_use.get(range * 16 + symbol) == Some(&true) {
+                    used_symbols |= 0x8000 >> symbol;
+                }
+            }
+            writer.write_bits(16, used_symbols);
+        }
+    }
+
+    writer.write_bits(3, n_tables as u32);
+    writer.write_bits(32, selectors.len() as u32);
+    write_selectors(&selectors, n_tables, &mut writer);
+    for table in lengths.iter() {
+        write_code_lengths(table, &mut writer);
+    }
+
+    for (group, &selector) in symbols.chunks(GROUP_SIZE).zip(selectors.iter()) {
+        let table = selector as usize;
+        for &symbol in group.iter() {
+            let symbol = symbol as usize;
+            writer.write_bits(lengths[table][symbol] as u32, codes[table][symbol]);
+        }
+    }
+
+    writer.finish()
+}
+
+pub fn huffman_decode_multi(content: &[u8]) -> Result<Vec<u16>, DlzipError> {
+    let mut reader = BitReader::new(content);
+
+    let n_ranges = ALPHABET_SIZE.div_ceil(16);
+    let mut symbol_of_dense: Vec<u16> = Vec::with_capacity(ALPHABET_SIZE);
+    let used_ranges = reader.read_bits(n_ranges as u32)?;
+    for range in 0..n_ranges {
+        if used_ranges & (1 << (n_ranges - 1 - range)) != 0 {
+            let used_symbols = reader.read_bits(16)?;
+            for symbol in 0..16 {
+                if used_symbols & (0x8000 >> symbol) != 0 {
+                    symbol_of_dense.push((range * 16 + symbol) as u16);
+                }
+            }
+        }
+    }
+    if symbol_of_dense.last() != Some(&HUFFMAN_MARKER) {
+        return Err(DlzipError::CorruptHuffmanTable);
+    }
+
+    let n_tables = reader.read_bits(3)? as usize;
+    if !(MIN_TABLES..=MAX_TABLES).contains(&n_tables) {
+        return Err(DlzipError::CorruptHuffmanTable);
+    }
+    let n_selectors = reader.read_bits(32)? as usize;
+    // Each selector takes at least one bit
+    if n_selectors > content.len() * 8 {
+        return Err(DlzipError::Truncated);
+    }
+    let selectors = read_selectors(n_selectors, n_tables, &mut reader)?;
+
+    let mut decoders: Vec<CanonicalDecoder> = Vec::with_capacity(n_tables);
+    for _ in 0..n_tables {
+        let lengths = read_code_lengths(symbol_of_dense.len(), &mut reader)?;
+        decoders.push(CanonicalDecoder::new(&lengths)?);
+    }
+
+    let mut result: Vec<u16> = Vec::new();
+    for &selector in selectors.iter() {
+        let decoder = &decoders[selector as usize];
+        for _ in 0..GROUP_SIZE {
+            let symbol = symbol_of_dense[decoder.decode(&mut reader)? as usize];
+            if symbol == HUFFMAN_MARKER {
+                return Ok(result);
+            }
+            result.push(symbol);
+        }
+    }
+
+    Err(DlzipError::CorruptData)
+}
+
+/// Encode content with a single Huffman table, no code is longer than max_len
+pub fn huffman_encode(content: &[u16], max_len: u8) -> Vec<u8> {
     let mut huffmantree = Huffman::new();
-    huffmantree.decode(content)
+    huffmantree.encode(content, max_len)
+}
+
+pub fn huffman_decode(content: &[u8]) -> Result<Vec<u16>, DlzipError> {
+    Huffman::new().decode(content)
 }
 
 #[cfg(test)]
 mod test {
-    use super::Huffman;
+    use super::{
+        binary_list, canonical_codes, code_lengths, huffman_decode, huffman_decode_multi,
+        huffman_encode, huffman_encode_multi, merge_symbol, multi_table_lengths, package_merge,
+        read_selectors, write_selectors, CanonicalDecoder, Huffman, HuffmanTreeNode,
+        DEFAULT_MAX_CODE_LEN, GROUP_SIZE, HUFFMAN_MARKER,
+    };
+    use crate::bitio::{BitReader, BitWriter};
+    use crate::error::DlzipError;
+    use std::time::Instant;
+
+    /// Tree walking decoder used before the lookup tables, kept to compare them
+    impl Huffman {
+        fn rebuild_tree_rec(node: &mut Box<HuffmanTreeNode>, mut code: Vec<u8>, symbol: usize) {
+            if code.is_empty() {
+                node.symbol = Some(symbol);
+            } else {
+                let bit = code.pop().unwrap();
+
+                if bit == 0 {
+                    if let Some(boxed_node) = node.left_child.as_mut() {
+                        Huffman::rebuild_tree_rec(boxed_node, code, symbol);
+                    } else {
+                        let left_child = HuffmanTreeNode::new();
+                        node.left_child = Some(Box::new(left_child));
+                        Huffman::rebuild_tree_rec(node.left_child.as_mut().unwrap(), code, symbol)
+                    }
+                } else if bit == 1 {
+                    if let Some(boxed_node) = node.right_child.as_mut() {
+                        Huffman::rebuild_tree_rec(boxed_node, code, symbol);
+                    } else {
+                        let right_child = HuffmanTreeNode::new();
+                        node.right_child = Some(Box::new(right_child));
+                        Huffman::rebuild_tree_rec(node.right_child.as_mut().unwrap(), code, symbol);
+                    }
+                }
+            }
+        }
+
+        fn rebuild_tree(&mut self, codes: &[usize]) {
+            let mut root = Box::new(HuffmanTreeNode::new());
+            let mut c = 0;
+            let mut last_length = 0;
+
+            for i in 0..codes.len() {
+                if i % 2 == 1 {
+                    continue;
+                } else {
+                    let symbol = codes[i];
+                    let length_diff = codes[i + 1];
+
+                    if length_diff > 0 {
+                        c <<= length_diff;
+                        last_length += length_diff;
+                    }
+
+                    let mut code = binary_list(c, last_length);
+                    code.reverse();
+                    Huffman::rebuild_tree_rec(&mut root, code, symbol);
+                    c += 1;
+                }
+            }
+
+            self.tree = Some(root);
+        }
+
+        fn tree_walk_decode(&mut self, content: &[u8]) -> Vec<u16> {
+            let codes_len = ((content[0] as usize) << 8) + content[1] as usize;
+            let can_codes = merge_symbol(&content[2..codes_len + 2]).unwrap();
+            self.rebuild_tree(&can_codes);
+
+            let mut result = Vec::new();
+            let mut node = self.tree.as_ref().unwrap().as_ref();
+
+            for mut element in content[codes_len + 2..].iter().cloned() {
+                for _ in 0..8 {
+                    if element & 0x80 == 0 {
+                        node = node.left_child.as_ref().unwrap().as_ref();
+                    } else {
+                        node = node.right_child.as_ref().unwrap().as_ref();
+                    }
+
+                    if let Some(symbol) = node.symbol {
+                        if symbol == HUFFMAN_MARKER as usize {
+                            return result;
+                        }
+                        result.push(symbol as u16);
+                        node = self.tree.as_ref().unwrap().as_ref();
+                    }
+
+                    element <<= 1;
+                }
+            }
+
+            panic!("Didn't found huffman marker");
+        }
+    }
 
     #[test]
     pub fn test_huffmantree() {
         let text_content = vec![15, 15, 15, 15, 16, 16, 231, 231, 192, 255];
         let mut huffmantree = Huffman::new();
-        let code = huffmantree.encode(&mut text_content.clone());
-        let mut n_huffmantree = Huffman::new();
-        let _decode = n_huffmantree.decode(code);
+        let code = huffmantree.encode(&text_content, DEFAULT_MAX_CODE_LEN);
+        let n_huffmantree = Huffman::new();
+        let _decode = n_huffmantree.decode(&code).unwrap();
         assert_eq!(text_content, _decode);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_canonical_decoder() {
+        // Codes longer than the lookup table are decoded with the limits
+        let mut lengths: Vec<u8> = (1..=15).collect();
+        lengths.push(15);
+        let codes = canonical_codes(&lengths);
+        let decoder = CanonicalDecoder::new(&lengths).unwrap();
+
+        let mut writer = BitWriter::new();
+        for symbol in (0..16).rev() {
+            writer.write_bits(lengths[symbol] as u32, codes[symbol]);
+        }
+        let content = writer.finish();
+        let mut reader = BitReader::new(&content);
+        for symbol in (0..16).rev() {
+            assert_eq!(decoder.decode(&mut reader), Ok(symbol as u16));
+        }
+
+        // 0b11 isn't the prefix of any code
+        let decoder = CanonicalDecoder::new(&[1, 2]).unwrap();
+        let mut reader = BitReader::new(&[0b1100_0000]);
+        assert_eq!(decoder.decode(&mut reader), Err(DlzipError::CorruptData));
+        assert_eq!(
+            CanonicalDecoder::new(&[1, 1, 1]).err(),
+            Some(DlzipError::CorruptHuffmanTable)
+        );
+    }
+
+    /// Symbols distributed like the output of ZRLE (never 0): a few very
+    /// frequent ones and a long tail
+    fn skewed_symbols(n: usize) -> Vec<u16> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..n)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                let rank = (state >> 11).trailing_zeros() as usize;
+                (rank * 7 + (state >> 58) as usize % 7 + 1).min(258) as u16
+            })
+            .collect()
+    }
+
+    #[ignore = "benchmark, run with cargo test --release -- --ignored --nocapture"]
+    #[test]
+    fn bench_huffman_decode() {
+        let text_content = skewed_symbols(4_000_000);
+        let code = huffman_encode(&text_content, DEFAULT_MAX_CODE_LEN);
+
+        let start = Instant::now();
+        let decode = Huffman::new().tree_walk_decode(&code);
+        let tree_walk = start.elapsed();
+        assert_eq!(decode, text_content);
+
+        let start = Instant::now();
+        let decode = huffman_decode(&code).unwrap();
+        let lookup = start.elapsed();
+        assert_eq!(decode, text_content);
+
+        let mega_symbols = text_content.len() as f64 / 1e6;
+        println!(
+            "tree walk: {:.1} Msymbols/s, lookup table: {:.1} Msymbols/s",
+            mega_symbols / tree_walk.as_secs_f64(),
+            mega_symbols / lookup.as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn test_code_lengths() {
+        let lengths = code_lengths(&[10, 1, 1, 5, 0], 20);
+        assert_eq!(lengths, vec![1, 4, 4, 2, 3]);
+        assert_eq!(
+            canonical_codes(&lengths),
+            vec![0b0, 0b1110, 0b1111, 0b10, 0b110]
+        );
+
+        // Fibonacci frequencies give the most unbalanced tree
+        let mut frequencies = vec![1, 1];
+        for i in 2..30 {
+            frequencies.push(frequencies[i - 1] + frequencies[i - 2]);
+        }
+        assert_eq!(code_lengths(&frequencies, 40).into_iter().max(), Some(29));
+        assert!(code_lengths(&frequencies, 17)
+            .into_iter()
+            .all(|len| len <= 17));
+    }
+
+    fn kraft_sum(lengths: &[u8]) -> f64 {
+        lengths.iter().map(|&len| 0.5_f64.powi(len as i32)).sum()
+    }
+
+    #[test]
+    fn test_package_merge() {
+        // Without a binding limit it gives Huffman code lengths
+        assert_eq!(package_merge(&[10, 1, 1, 5, 1], 20), vec![1, 4, 4, 2, 3]);
+        assert_eq!(package_merge(&[3, 7], 1), vec![1, 1]);
+        assert_eq!(package_merge(&[1, 1, 1, 1], 2), vec![2, 2, 2, 2]);
+
+        let mut weights: Vec<u64> = vec![1, 1];
+        for i in 2..40 {
+            weights.push(weights[i - 1] + weights[i - 2]);
+        }
+        for max_len in [6, 9, 12, 17, 20] {
+            let lengths = package_merge(&weights, max_len);
+            assert_eq!(lengths.iter().copied().max(), Some(max_len));
+            assert_eq!(kraft_sum(&lengths), 1.0);
+            // Heavier symbols never get longer codes
+            assert!(lengths.windows(2).all(|pair| pair[0] >= pair[1]));
+        }
+    }
+
+    #[test]
+    fn test_huffman_length_limited() {
+        // Fibonacci occurences give codes up to 24 bits without limit
+        let mut text_content: Vec<u16> = Vec::new();
+        let (mut a, mut b) = (1, 1);
+        for symbol in 1..26 {
+            text_content.extend(std::iter::repeat_n(symbol, a));
+            (a, b) = (b, a + b);
+        }
+
+        for max_len in [9, 12, DEFAULT_MAX_CODE_LEN] {
+            let mut huffmantree = Huffman::new();
+            let code = huffmantree.encode(&text_content, max_len);
+            assert!(huffmantree
+                .codes
+                .values()
+                .all(|code| code.len() <= max_len as usize));
+            assert_eq!(Huffman::new().decode(&code).unwrap(), text_content);
+
+            let code = huffman_encode_multi(&text_content, max_len);
+            assert_eq!(huffman_decode_multi(&code).unwrap(), text_content);
+        }
+    }
+
+    #[test]
+    fn test_selectors() {
+        let selectors = vec![0, 0, 3, 1, 3, 2, 0];
+        let mut writer = BitWriter::new();
+        write_selectors(&selectors, 4, &mut writer);
+        let content = writer.finish();
+
+        let mut reader = BitReader::new(&content);
+        assert_eq!(read_selectors(7, 4, &mut reader).unwrap(), selectors);
+    }
+
+    #[test]
+    fn test_multi_table_lengths() {
+        // Two halves using disjoint symbols should select different tables
+        let mut symbols: Vec<u16> = (0..10 * GROUP_SIZE).map(|el| (el % 3) as u16).collect();
+        symbols.extend((0..10 * GROUP_SIZE).map(|el| 3 + (el % 5) as u16));
+
+        let (lengths, selectors) = multi_table_lengths(&symbols, 8, 2, 17);
+        assert_eq!(lengths.len(), 2);
+        assert_eq!(selectors.len(), 20);
+        assert!(selectors[..10].iter().all(|&el| el == selectors[0]));
+        assert!(selectors[10..].iter().all(|&el| el == selectors[10]));
+        assert_ne!(selectors[0], selectors[10]);
+    }
+
+    #[test]
+    fn test_huffman_multi() {
+        let mut text_content = vec![15, 15, 15, 15, 16, 16, 231, 231, 192, 255, 257, 258, 1];
+        text_content.extend((0..2000usize).map(|el| 1 + (el * el % 7) as u16));
+        text_content.extend((0..2000).map(|el| 200 + el % 50));
+
+        let code = huffman_encode_multi(&text_content, DEFAULT_MAX_CODE_LEN);
+        let decode = huffman_decode_multi(&code).unwrap();
+        assert_eq!(text_content, decode);
+    }
+}
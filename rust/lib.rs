@@ -1,53 +1,414 @@
-use pyo3::prelude::*;
-
-mod bwt;
-pub mod content;
-mod huffman;
-mod mtf;
-mod z_rle;
-
-#[pyfunction]
-fn _compress(text_content: Vec<usize>) -> PyResult<Vec<u8>> {
-    let code = bwt::bwt_encode(text_content);
-    let code = mtf::mtf_encode(code);
-    let code = z_rle::zrle_encode(code);
-    Ok(huffman::huffman_encode(code))
+//! dlzip2 compression: a BWT, MTF, zero run length and Huffman pipeline
+//! applied block by block, written in a self-describing .dlz2 container.
+//! bzip2 streams can be written and read too.
+//!
+//! ```
+//! use std::io::{Read, Write};
+//!
+//! let text = b"Some useless text that should be compressed";
+//! let stream = dlzip2::compress(text);
+//! assert_eq!(dlzip2::decompress(&stream).unwrap(), text);
+//!
+//! // The same, with bounded memory
+//! let mut encoder = dlzip2::Dlzip2Encoder::new(Vec::new()).unwrap();
+//! encoder.write_all(text).unwrap();
+//! let stream = encoder.finish().unwrap();
+//! let mut result = Vec::new();
+//! dlzip2::Dlzip2Decoder::new(&stream[..]).unwrap().read_to_end(&mut result).unwrap();
+//! assert_eq!(result, text);
+//! ```
+//!
+//! The PyO3 bindings of the Python package are behind the `python` feature.
+
+use std::io::{Read, Write};
+
+pub mod bitio;
+pub mod bwt;
+pub mod bzip2;
+pub mod container;
+pub mod crc;
+mod decoder;
+mod encoder;
+pub mod error;
+pub mod huffman;
+pub mod mtf;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(test)]
+#[path = "tests/common/mod.rs"]
+mod test_common;
+pub mod z_rle;
+
+pub use container::StreamInfo;
+use container::{
+    BlockHeader, Frame, Header, StreamEnd, ALL_STAGES, END_FRAME_SIZE, FLAG_BWT, FLAG_MTF,
+    FLAG_MULTI_TABLE, FLAG_ZRLE, UNKNOWN_LEN,
+};
+pub use decoder::{Dlzip2Decoder, PushDecoder};
+pub use encoder::Dlzip2Encoder;
+pub use error::{DlzipError, OptionsError, VerifyError};
+
+/// Most threads used per cpu, each one holds a block, so more would only
+/// use memory
+pub const THREADS_PER_CPU: usize = 4;
+
+/// Parameters of the compression
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Number of bytes of content in each block
+    pub block_size: usize,
+    /// Code each block with several Huffman tables instead of one
+    pub multi_table: bool,
+    /// Longest Huffman code, between huffman::MIN_CODE_LEN_LIMIT and
+    /// huffman::MAX_DECODE_LEN
+    pub max_code_len: u8,
+    /// Number of blocks compressed in parallel, each on its own thread, 0 for
+    /// one per available cpu, capped to Options::max_threads(). The stream
+    /// doesn't depend on it
+    pub threads: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            block_size: bwt::MAX_VEC_SIZE,
+            multi_table: false,
+            max_code_len: huffman::DEFAULT_MAX_CODE_LEN,
+            threads: 1,
+        }
+    }
+}
+
+impl Options {
+    /// Check every option is in its range, return the first invalid one
+    pub fn check(&self) -> Result<(), OptionsError> {
+        if self.block_size == 0 || self.block_size > u32::MAX as usize {
+            return Err(OptionsError::BlockSize(self.block_size));
+        }
+        if !(huffman::MIN_CODE_LEN_LIMIT..=huffman::MAX_DECODE_LEN).contains(&self.max_code_len) {
+            return Err(OptionsError::MaxCodeLen(self.max_code_len));
+        }
+        Ok(())
+    }
+
+    /// Largest number of threads used, more are capped to it
+    pub fn max_threads() -> usize {
+        THREADS_PER_CPU * cpu_count()
+    }
+
+    /// Number of threads to use, threads with 0 replaced by the number of cpus
+    /// and capped to Options::max_threads()
+    pub fn thread_count(&self) -> usize {
+        if self.threads == 0 {
+            cpu_count()
+        } else {
+            self.threads.min(Options::max_threads())
+        }
+    }
+
+    fn flags(&self) -> u8 {
+        if self.multi_table {
+            ALL_STAGES | FLAG_MULTI_TABLE
+        } else {
+            ALL_STAGES
+        }
+    }
 }
 
-#[pyfunction]
-fn _decompress(text_content: Vec<u8>) -> PyResult<Vec<u8>> {
-    let decode = huffman::huffman_decode(text_content);
-    let decode = z_rle::zrle_decode(decode);
-    let decode = mtf::mtf_decode(decode);
-    Ok(bwt::bwt_decode(decode))
+fn cpu_count() -> usize {
+    std::thread::available_parallelism().map_or(1, |count| count.get())
 }
 
-#[pymodule]
-fn _dlzip2(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(_compress, m)?)?;
-    m.add_function(wrap_pyfunction!(_decompress, m)?)?;
+/// Encode a block and write its frame into output, return the block crc
+fn encode_block(block: &[u8], options: &Options, output: &mut Vec<u8>) -> u32 {
+    let block_crc = crc::block_crc(block);
+
+    let (last_column, primary_index) = bwt::bwt_encode_block(block);
+    let code = mtf::mtf_encode(&last_column);
+    let code = z_rle::zrle_encode(&code);
+    let mut payload = if options.multi_table {
+        huffman::huffman_encode_multi(&code, options.max_code_len)
+    } else {
+        huffman::huffman_encode(&code, options.max_code_len)
+    };
+
+    let block_header = BlockHeader {
+        block_len: block.len() as u32,
+        primary_index: primary_index as u32,
+        block_crc,
+        payload_len: payload.len() as u32,
+    };
+    block_header.write(output);
+    output.append(&mut payload);
+
+    block_crc
+}
+
+/// Decode the payload of the block_idx-th block and check its crc
+fn decode_block(
+    header: &Header,
+    block_idx: usize,
+    block_header: &BlockHeader,
+    payload: &[u8],
+) -> Result<Vec<u8>, DlzipError> {
+    if block_header.block_len > header.block_size {
+        return Err(DlzipError::CorruptBlockHeader);
+    }
+    let symbols = if header.has_stage(FLAG_MULTI_TABLE) {
+        huffman::huffman_decode_multi(payload)?
+    } else {
+        huffman::huffman_decode(payload)?
+    };
+    let mut decode = if header.has_stage(FLAG_ZRLE) {
+        z_rle::zrle_decode(&symbols, block_header.block_len as usize)?
+    } else {
+        symbols
+            .into_iter()
+            .map(|symbol| u8::try_from(symbol).map_err(|_| DlzipError::CorruptData))
+            .collect::<Result<Vec<u8>, DlzipError>>()?
+    };
+    if header.has_stage(FLAG_MTF) {
+        decode = mtf::mtf_decode(&decode);
+    }
+
+    if decode.len() != block_header.block_len as usize {
+        return Err(DlzipError::LengthMismatch {
+            expected: block_header.block_len as u64,
+            found: decode.len() as u64,
+        });
+    }
+
+    let decode = if header.has_stage(FLAG_BWT) {
+        bwt::bwt_decode_block(&decode, block_header.primary_index as usize)?
+    } else {
+        decode
+    };
+
+    let block_crc = crc::block_crc(&decode);
+    if block_crc != block_header.block_crc {
+        return Err(DlzipError::ChecksumMismatch {
+            block: Some(block_idx),
+            expected: block_header.block_crc,
+            found: block_crc,
+        });
+    }
+
+    Ok(decode)
+}
+
+/// Check the end frame against the decoded blocks : their combined crc,
+/// their number and their total length, which the header records too when
+/// it is known
+fn check_end(
+    header: &Header,
+    end: &StreamEnd,
+    stream_crc: u32,
+    block_count: u64,
+    decoded_len: u64,
+) -> Result<(), DlzipError> {
+    if stream_crc != end.stream_crc {
+        return Err(DlzipError::ChecksumMismatch {
+            block: None,
+            expected: end.stream_crc,
+            found: stream_crc,
+        });
+    }
+
+    // Version 1 end frames don't record the block count
+    if end.block_count != UNKNOWN_LEN && block_count != end.block_count {
+        return Err(DlzipError::BlockCountMismatch {
+            expected: end.block_count,
+            found: block_count,
+        });
+    }
+    for expected in [header.original_len, end.original_len] {
+        if expected != UNKNOWN_LEN && decoded_len != expected {
+            return Err(DlzipError::LengthMismatch {
+                expected,
+                found: decoded_len,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// LegacyLayout when the first frame of a version 1 stream, starting content,
+/// couldn't be read because the stream has an older layout
+fn legacy_layout_error(version: u8, block_idx: usize, content: impl Read) -> Option<DlzipError> {
+    let legacy = version == 1 && block_idx == 0 && container::is_legacy_layout(content);
+    legacy.then_some(DlzipError::LegacyLayout)
+}
+
+/// Compress content with the given options, fails if an option is out of its
+/// range
+pub fn compress_with(content: &[u8], options: &Options) -> Result<Vec<u8>, OptionsError> {
+    options.check()?;
+
+    // Writing into a Vec can't fail
+    let mut encoder =
+        Dlzip2Encoder::start(Vec::new(), options.clone(), content.len() as u64).unwrap();
+    encoder.write_all(content).unwrap();
+    Ok(encoder.finish().unwrap())
+}
+
+pub fn compress(content: &[u8]) -> Vec<u8> {
+    // The default options are valid
+    compress_with(content, &Options::default()).unwrap()
+}
+
+/// Decompress a .dlz2 stream, or a bzip2 stream recognised by its signature
+pub fn decompress(text_content: &[u8]) -> Result<Vec<u8>, DlzipError> {
+    if bzip2::is_bzip2(text_content) {
+        return bzip2::decompress(text_content);
+    }
+
+    // original_len isn't trusted to preallocate, a corrupt one could abort the allocation
+    let mut result = Vec::new();
+    decode_stream(text_content, |mut block| result.append(&mut block)).map_err(|err| err.error)?;
+
+    Ok(result)
+}
+
+/// Decode a .dlz2 or bzip2 stream without keeping its content, checking the
+/// checksum of every block and of the stream. Return the decoded length
+pub fn verify(text_content: &[u8]) -> Result<u64, VerifyError> {
+    if bzip2::is_bzip2(text_content) {
+        return Ok(bzip2::decompress(text_content)?.len() as u64);
+    }
+
+    let mut decoded_len = 0;
+    decode_stream(text_content, |block| decoded_len += block.len() as u64)?;
+    Ok(decoded_len)
+}
+
+/// Read the metadata of a .dlz2 stream from its header and end frame, without
+/// decoding it. Only a decompression or verify finds the corrupt blocks
+pub fn stream_info(text_content: &[u8]) -> Result<StreamInfo, DlzipError> {
+    let tail_start = text_content.len().saturating_sub(END_FRAME_SIZE);
+    StreamInfo::read(
+        text_content,
+        &text_content[tail_start..],
+        text_content.len() as u64,
+    )
+}
+
+/// Decode the blocks of a .dlz2 stream one after another and give them to
+/// on_block, the errors of a block record its index
+fn decode_stream(
+    text_content: &[u8],
+    mut on_block: impl FnMut(Vec<u8>),
+) -> Result<(), VerifyError> {
+    let (header, mut stream) = Header::read(text_content)?;
+
+    let mut stream_crc = 0;
+    let mut decoded_len = 0;
+    let mut block_idx = 0;
+    let end = loop {
+        // The errors past the tag of a block frame are found in that block
+        let in_block = stream.first() == Some(&container::BLOCK_TAG);
+        let frame_error = |error| match legacy_layout_error(header.version, block_idx, stream) {
+            Some(error) => error.into(),
+            None => VerifyError {
+                block: in_block.then_some(block_idx),
+                error,
+            },
+        };
+        let (frame, rest) = container::read_frame(stream, header.version).map_err(frame_error)?;
+        match frame {
+            Frame::Block(block_header, payload) => {
+                let block = decode_block(&header, block_idx, &block_header, payload);
+                let block = block.map_err(frame_error)?;
+                stream_crc = crc::combine_crc(stream_crc, block_header.block_crc);
+                decoded_len += block.len() as u64;
+                on_block(block);
+            }
+            Frame::End(end) => break end,
+        }
+        block_idx += 1;
+        stream = rest;
+    };
+
+    check_end(&header, &end, stream_crc, block_idx as u64, decoded_len)?;
+
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
+    use std::io::Write;
+
     use crate::huffman;
 
-    use super::{bwt, mtf, z_rle};
+    use super::container::{
+        StreamInfo, ALL_STAGES, BLOCK_HEADER_SIZE, END_FRAME_SIZE, FLAG_MULTI_TABLE,
+        FORMAT_VERSION, HEADER_SIZE, V1_END_FRAME_SIZE, V1_HEADER_SIZE,
+    };
+    use super::{
+        bwt, compress, compress_with, container, decompress, mtf, stream_info, verify, z_rle,
+        Dlzip2Encoder, DlzipError, Options, OptionsError, VerifyError,
+    };
+
+    /// b"banana bandana" in blocks of 8 bytes, as written by the version 1 builds
+    #[rustfmt::skip]
+    pub(crate) const V1_STREAM: [u8; 96] = [
+        // header
+        0x44, 0x4c, 0x5a, 0x32, 0x01, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e,
+        // block 0
+        0x42, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x04, 0x6f, 0x9b, 0x2f, 0x4b, 0x00,
+        0x00, 0x00, 0x18, 0x00, 0x12, 0x00, 0x01, 0x02, 0x02, 0x01, 0x03, 0x00, 0x23, 0x00,
+        0x61, 0x00, 0x00, 0x03, 0x00, 0x63, 0x01, 0x6e, 0x00, 0xbe, 0x74, 0x62, 0xc0,
+        // block 1
+        0x42, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x01, 0x3d, 0x34, 0x67, 0x48, 0x00,
+        0x00, 0x00, 0x13, 0x00, 0x0f, 0x63, 0x02, 0x00, 0x03, 0x00, 0x64, 0x01, 0x6e, 0x00,
+        0x00, 0x01, 0x00, 0x00, 0x02, 0x00, 0x97, 0x1d,
+        // end frame
+        0x45, 0xe2, 0x02, 0x39, 0xde,
+    ];
+
+    /// b"banana bandana" as written by the first version 1 build, a Huffman
+    /// stream without frames
+    #[rustfmt::skip]
+    pub(crate) const V1_UNFRAMED_STREAM: [u8; 51] = [
+        // header
+        0x44, 0x4c, 0x5a, 0x32, 0x01, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e,
+        // Huffman stream
+        0x00, 0x1c, 0x61, 0x03, 0x05, 0x00, 0x02, 0x00, 0x00, 0x01, 0x00, 0x25, 0x01, 0x01,
+        0x00, 0x63, 0x00, 0x00, 0x00, 0x00, 0x65, 0x00, 0x6e, 0x00, 0x00, 0x03, 0x00, 0x00,
+        0x02, 0x00, 0x1a, 0xeb, 0x25, 0x5c, 0x22, 0xff, 0x80,
+    ];
+
+    /// V1_STREAM as written by the build that framed the blocks without crc
+    #[rustfmt::skip]
+    pub(crate) const V1_UNCHECKED_STREAM: [u8; 84] = [
+        // header
+        0x44, 0x4c, 0x5a, 0x32, 0x01, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e,
+        // block 0
+        0x42, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x18,
+        0x00, 0x12, 0x00, 0x01, 0x02, 0x02, 0x01, 0x03, 0x00, 0x23, 0x00, 0x61, 0x00, 0x00,
+        0x03, 0x00, 0x63, 0x01, 0x6e, 0x00, 0xbe, 0x74, 0x62, 0xc0,
+        // block 1
+        0x42, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x13,
+        0x00, 0x0f, 0x63, 0x02, 0x00, 0x03, 0x00, 0x64, 0x01, 0x6e, 0x00, 0x00, 0x01, 0x00,
+        0x00, 0x02, 0x00, 0x97, 0x1d,
+        // end tag
+        0x45,
+    ];
 
     #[test]
     fn test_bwt_mtf_zrle_chain() {
         let text_content = vec![15, 15, 15, 15, 16, 16, 231, 231, 192, 255];
 
-        let mut code = bwt::bwt_encode(text_content.clone());
-        code = mtf::mtf_encode(code);
-        code = z_rle::zrle_encode(code);
+        let (code, primary_index) = bwt::bwt_encode_block(&text_content);
+        let code = mtf::mtf_encode(&code);
+        let code = z_rle::zrle_encode(&code);
 
         println!("{:?}", code);
 
-        let mut decode = z_rle::zrle_decode(code);
-        decode = mtf::mtf_decode(decode);
-        let _decode = bwt::bwt_decode(decode);
+        let decode = z_rle::zrle_decode(&code, usize::MAX).unwrap();
+        let decode = mtf::mtf_decode(&decode);
+        let _decode = bwt::bwt_decode_block(&decode, primary_index).unwrap();
+        assert_eq!(_decode, [15, 15, 15, 15, 16, 16, 231, 231, 192, 255]);
     }
 
     #[test]
@@ -55,19 +416,327 @@ mod test {
         let text_content = vec![15, 15, 15, 15, 16, 16, 231, 231, 192, 255];
         let text_len = text_content.len();
 
-        let mut code = bwt::bwt_encode(text_content.clone());
-        code = mtf::mtf_encode(code);
-        code = z_rle::zrle_encode(code);
-        let code = huffman::huffman_encode(code);
+        let (code, primary_index) = bwt::bwt_encode_block(&text_content);
+        let code = mtf::mtf_encode(&code);
+        let code = z_rle::zrle_encode(&code);
+        let code = huffman::huffman_encode(&code, huffman::DEFAULT_MAX_CODE_LEN);
 
         println!(
             "Compression ratio: {} %",
-            (code.len() as f64 / text_len as f64) * 100 as f64
+            (code.len() as f64 / text_len as f64) * 100_f64
         );
 
-        let mut decode = huffman::huffman_decode(code);
-        decode = z_rle::zrle_decode(decode);
-        decode = mtf::mtf_decode(decode);
-        let _decode = bwt::bwt_decode(decode);
+        let decode = huffman::huffman_decode(&code).unwrap();
+        let decode = z_rle::zrle_decode(&decode, usize::MAX).unwrap();
+        let decode = mtf::mtf_decode(&decode);
+        let _decode = bwt::bwt_decode_block(&decode, primary_index).unwrap();
+        assert_eq!(_decode, text_content);
+    }
+
+    #[test]
+    fn test_compress_decompress() {
+        let text = "Some useless text that should be compressed and decompressed";
+
+        let code = compress(text.as_bytes());
+        assert_eq!(&code[..4], b"DLZ2");
+
+        let decode = decompress(&code).unwrap();
+        assert_eq!(decode, text.as_bytes());
+    }
+
+    #[test]
+    fn test_decompress_foreign_stream() {
+        assert_eq!(decompress(b"PK\x03\x04"), Err(DlzipError::BadMagic));
+        assert_eq!(decompress(b""), Err(DlzipError::BadMagic));
+    }
+
+    #[test]
+    fn test_compress_multiple_blocks() {
+        let text = "mmiissiissiippii, some text spanning multiple small blocks\0\0\0";
+
+        let options = Options {
+            block_size: 7,
+            ..Options::default()
+        };
+        let code = compress_with(text.as_bytes(), &options).unwrap();
+        let decode = decompress(&code).unwrap();
+        assert_eq!(decode, text.as_bytes());
+
+        let code = compress(&[]);
+        assert_eq!(decompress(&code).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_compress_multi_table() {
+        let text = "Some useless text that should be compressed with several tables, ".repeat(50);
+
+        let options = Options {
+            block_size: 1000,
+            multi_table: true,
+            ..Options::default()
+        };
+        let code = compress_with(text.as_bytes(), &options).unwrap();
+        assert_eq!(decompress(&code).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn test_compress_invalid_options() {
+        let options = Options {
+            block_size: 0,
+            ..Options::default()
+        };
+        assert_eq!(
+            compress_with(b"some text", &options),
+            Err(OptionsError::BlockSize(0))
+        );
+
+        let options = Options {
+            max_code_len: 0,
+            ..Options::default()
+        };
+        let error = compress_with(b"some text", &options).unwrap_err();
+        assert_eq!(error, OptionsError::MaxCodeLen(0));
+        assert!(error
+            .to_string()
+            .starts_with("max_code_len should be between"));
+    }
+
+    /// The layout of the current version, a change of these bytes must bump
+    /// FORMAT_VERSION and keep the previous version readable
+    #[test]
+    fn test_stream_layout() {
+        let options = Options {
+            block_size: 8,
+            ..Options::default()
+        };
+        let code = compress_with(b"banana bandana", &options).unwrap();
+        let mut expected = vec![
+            // header, with the block size and its crc
+            0x44, 0x4c, 0x5a, 0x32, 0x02, 0x0f, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x0e, 0x02, 0x58, 0x82, 0x63,
+        ];
+        // The blocks are framed as in version 1
+        expected.extend_from_slice(&V1_STREAM[V1_HEADER_SIZE..V1_STREAM.len() - V1_END_FRAME_SIZE]);
+        expected.extend_from_slice(&[
+            // end frame, with the block count and the original length
+            0x45, 0xe2, 0x02, 0x39, 0xde, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e,
+        ]);
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn test_decompress_v1() {
+        assert_eq!(decompress(&V1_STREAM).unwrap(), b"banana bandana");
+        assert_eq!(verify(&V1_STREAM), Ok(14));
+
+        // Written by the encoder, with an unknown length
+        let mut streamed = V1_STREAM;
+        streamed[6..V1_HEADER_SIZE].fill(0xff);
+        assert_eq!(decompress(&streamed).unwrap(), b"banana bandana");
+
+        assert_eq!(
+            decompress(&V1_STREAM[..V1_STREAM.len() - 1]),
+            Err(DlzipError::Truncated)
+        );
+        let mut corrupt = V1_STREAM;
+        corrupt[V1_STREAM.len() - 1] ^= 1;
+        assert!(matches!(
+            decompress(&corrupt),
+            Err(DlzipError::ChecksumMismatch { block: None, .. })
+        ));
+        // Version 1 doesn't record what stream_info returns
+        assert_eq!(
+            stream_info(&V1_STREAM),
+            Err(DlzipError::UnsupportedVersion(1))
+        );
+    }
+
+    #[test]
+    fn test_decompress_legacy_v1() {
+        for stream in [&V1_UNFRAMED_STREAM[..], &V1_UNCHECKED_STREAM] {
+            assert_eq!(decompress(stream), Err(DlzipError::LegacyLayout));
+            assert_eq!(verify(stream), Err(VerifyError::from(DlzipError::LegacyLayout)));
+            assert!(container::is_legacy_layout(&stream[V1_HEADER_SIZE..]));
+        }
+        assert!(!container::is_legacy_layout(&V1_STREAM[V1_HEADER_SIZE..]));
+        assert!(!container::is_legacy_layout(&[][..]));
+        // A lone end tag must end the stream
+        assert!(!container::is_legacy_layout(&b"E "[..]));
+        // Damaged streams of the current layout keep their own error
+        assert_eq!(
+            decompress(&V1_UNCHECKED_STREAM[..V1_UNCHECKED_STREAM.len() - 1]),
+            Err(DlzipError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_decompress_truncated() {
+        let code = compress(b"some text");
+
+        assert_eq!(
+            decompress(&code[..code.len() - 1]),
+            Err(DlzipError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_decompress_checksum() {
+        let options = Options {
+            block_size: 8,
+            ..Options::default()
+        };
+        let code = compress_with(b"mmiissiissiippii", &options).unwrap();
+
+        // Corrupt the primary index of the second block
+        let second_block = HEADER_SIZE + BLOCK_HEADER_SIZE + code[HEADER_SIZE + 16] as usize;
+        let mut corrupted = code.clone();
+        corrupted[second_block + 8] ^= 1;
+        assert!(matches!(
+            decompress(&corrupted),
+            Err(DlzipError::ChecksumMismatch { block: Some(1), .. })
+        ));
+
+        // Corrupt the stream crc, which follows the tag of the end frame
+        let end_frame = code.len() - END_FRAME_SIZE;
+        let mut corrupted = code.clone();
+        corrupted[end_frame + 1] ^= 1;
+        assert!(matches!(
+            decompress(&corrupted),
+            Err(DlzipError::ChecksumMismatch { block: None, .. })
+        ));
+
+        // Then the block count and the original length
+        let mut corrupted = code.clone();
+        corrupted[end_frame + 12] ^= 1;
+        assert_eq!(
+            decompress(&corrupted),
+            Err(DlzipError::BlockCountMismatch {
+                expected: 3,
+                found: 2
+            })
+        );
+        let mut corrupted = code.clone();
+        corrupted[end_frame + 20] ^= 1;
+        assert_eq!(
+            decompress(&corrupted),
+            Err(DlzipError::LengthMismatch {
+                expected: 17,
+                found: 16
+            })
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let options = Options {
+            block_size: 8,
+            ..Options::default()
+        };
+        let code = compress_with(b"mmiissiissiippii", &options).unwrap();
+        assert_eq!(verify(&code), Ok(16));
+        let bz2_code = super::bzip2::compress(b"some text", 9).unwrap();
+        assert_eq!(verify(&bz2_code), Ok(9));
+
+        // Errors found in a block record its index, whatever they are
+        let second_block = HEADER_SIZE + BLOCK_HEADER_SIZE + code[HEADER_SIZE + 16] as usize;
+        for idx in second_block + BLOCK_HEADER_SIZE..second_block + BLOCK_HEADER_SIZE + 4 {
+            let mut corrupted = code.clone();
+            corrupted[idx] ^= 0xff;
+            assert_eq!(verify(&corrupted).unwrap_err().block, Some(1));
+        }
+
+        let error = verify(&code[..code.len() - 1]).unwrap_err();
+        assert_eq!(
+            error,
+            VerifyError {
+                block: None,
+                error: DlzipError::Truncated
+            }
+        );
+        let error = VerifyError {
+            block: Some(3),
+            error: DlzipError::CorruptData,
+        };
+        assert_eq!(error.to_string(), "corrupt compressed data in block 3");
+    }
+
+    #[test]
+    fn test_stream_info() {
+        let options = Options {
+            block_size: 8,
+            multi_table: true,
+            ..Options::default()
+        };
+        let code = compress_with(b"mmiissiissiippii!", &options).unwrap();
+        let info = stream_info(&code).unwrap();
+        assert_eq!(
+            info,
+            StreamInfo {
+                version: FORMAT_VERSION,
+                flags: ALL_STAGES | FLAG_MULTI_TABLE,
+                block_size: 8,
+                block_count: 3,
+                compressed_len: code.len() as u64,
+                original_len: 17,
+                stream_crc: info.stream_crc,
+            }
+        );
+
+        // Streams record their length only in the end frame
+        let mut encoder = Dlzip2Encoder::with_options(Vec::new(), options).unwrap();
+        encoder.write_all(b"mmiissiissiippii!").unwrap();
+        assert_eq!(stream_info(&encoder.finish().unwrap()), Ok(info.clone()));
+
+        // Only the header and the end frame are read
+        let head = &code[..HEADER_SIZE];
+        let tail = &code[code.len() - END_FRAME_SIZE..];
+        assert_eq!(StreamInfo::read(head, tail, code.len() as u64), Ok(info));
+
+        assert_eq!(
+            stream_info(&code[..code.len() - 1]),
+            Err(DlzipError::Truncated)
+        );
+        assert_eq!(
+            stream_info(&code[..HEADER_SIZE]),
+            Err(DlzipError::Truncated)
+        );
+        assert_eq!(stream_info(b"BZh9"), Err(DlzipError::BadMagic));
+    }
+
+    #[test]
+    fn test_decompress_bzip2() {
+        let code = super::bzip2::compress(b"some text", 9).unwrap();
+        assert_eq!(decompress(&code).unwrap(), b"some text");
+    }
+
+    #[test]
+    fn test_decompress_corrupt_never_panics() {
+        let text = b"Some useless text, some useless text, and zeros \0\0\0\0\0\0\0".repeat(3);
+        let mut streams = vec![super::bzip2::compress(&text, 9).unwrap()];
+        for multi_table in [false, true] {
+            let options = Options {
+                block_size: 70,
+                multi_table,
+                ..Options::default()
+            };
+            streams.push(compress_with(&text, &options).unwrap());
+        }
+
+        for code in streams.iter() {
+            for len in 0..code.len() {
+                assert!(decompress(&code[..len]).is_err());
+            }
+            // Flipping padding bits can leave the content intact, anything else is an error
+            for idx in 0..code.len() {
+                for mask in [0x01, 0x10, 0x80, 0xff] {
+                    let mut corrupted = code.clone();
+                    corrupted[idx] ^= mask;
+                    if let Ok(decode) = decompress(&corrupted) {
+                        assert_eq!(decode, text);
+                    }
+                }
+            }
+        }
     }
 }
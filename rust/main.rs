@@ -0,0 +1,369 @@
+//! dlzip2 command line, de/compressing files as bzip2 does. Its exit codes
+//! are the ones of bzip2
+
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::{env, panic};
+
+use dlzip2::container::{self, END_FRAME_SIZE, HEADER_SIZE};
+use dlzip2::{
+    bzip2, decompress, stream_info, Dlzip2Decoder, Dlzip2Encoder, StreamInfo, VerifyError,
+};
+
+use cli::{Config, Mode, STDIN_PATH};
+
+mod cli;
+
+const EXIT_OK: u8 = 0;
+/// Missing input file, existing output file, invalid flag, io error...
+const EXIT_ENVIRONMENT: u8 = 1;
+const EXIT_CORRUPT_INPUT: u8 = 2;
+/// A panic, which is a bug
+const EXIT_INTERNAL_ERROR: u8 = 3;
+
+/// Why a file couldn't be processed
+enum Failure {
+    Environment(String),
+    Corrupt(VerifyError),
+}
+
+impl Failure {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Failure::Environment(_) => EXIT_ENVIRONMENT,
+            Failure::Corrupt(_) => EXIT_CORRUPT_INPUT,
+        }
+    }
+}
+
+fn io_failure(context: &str, path: &Path, err: io::Error) -> Failure {
+    Failure::Environment(format!("{} {}: {}", context, path.display(), err))
+}
+
+/// Failure of a streaming de/compression of name, the decoder reports
+/// corrupt streams as io errors wrapping a VerifyError
+fn stream_failure(err: io::Error, name: &str) -> Failure {
+    match err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<VerifyError>())
+    {
+        Some(verify_error) => Failure::Corrupt(verify_error.clone()),
+        None => Failure::Environment(format!("{}: {}", name, err)),
+    }
+}
+
+/// Writer counting the bytes written into it
+struct CountingWriter<W: Write> {
+    writer: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = match Config::parse(&args) {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("dlzip2: {}\n\n{}", message, cli::USAGE);
+            return ExitCode::from(EXIT_ENVIRONMENT);
+        }
+    };
+
+    if config.help {
+        print!("{}", cli::USAGE);
+        return ExitCode::from(EXIT_OK);
+    }
+    if config.version {
+        println!("dlzip2 {}", env!("CARGO_PKG_VERSION"));
+        return ExitCode::from(EXIT_OK);
+    }
+    if config.mode == Mode::Compress
+        && !config.list
+        && config.writes_stdout()
+        && io::stdout().is_terminal()
+        && !config.force
+    {
+        eprintln!("dlzip2: compressed data won't be written to a terminal, use -f to force it");
+        return ExitCode::from(EXIT_ENVIRONMENT);
+    }
+    if let Err(err) = config.options().check() {
+        eprintln!("dlzip2: {}", err);
+        return ExitCode::from(EXIT_ENVIRONMENT);
+    }
+
+    match panic::catch_unwind(|| run(&config)) {
+        Ok(exit_code) => ExitCode::from(exit_code),
+        Err(_) => {
+            eprintln!("dlzip2: internal error, this is a bug");
+            ExitCode::from(EXIT_INTERNAL_ERROR)
+        }
+    }
+}
+
+/// Process or list every file, return the exit code of the worst failure
+fn run(config: &Config) -> u8 {
+    let mut exit_code = EXIT_OK;
+    let mut listed = Vec::new();
+    for file_path in config.file_paths.iter() {
+        let result = if config.list {
+            read_stream_info(file_path).map(|info| {
+                if listed.is_empty() {
+                    println!("{}", container::LIST_HEADER);
+                }
+                println!("{}", container::list_line(&info, file_path));
+                listed.push(info);
+            })
+        } else if file_path == STDIN_PATH {
+            process_stdin(config)
+        } else {
+            process_file(config, Path::new(file_path))
+        };
+        match result {
+            // Test mode reports every file on the standard output
+            Ok(()) if config.mode == Mode::Test && !config.quiet => println!("{}: OK", file_path),
+            Ok(()) => (),
+            Err(failure) => {
+                match &failure {
+                    Failure::Environment(message) => eprintln!("dlzip2: {}", message),
+                    Failure::Corrupt(err) if config.mode == Mode::Test => {
+                        println!("{}: FAILED, {}", file_path, err)
+                    }
+                    Failure::Corrupt(err) => eprintln!("dlzip2: {}: {}", file_path, err),
+                }
+                exit_code = exit_code.max(failure.exit_code());
+            }
+        }
+    }
+    if listed.len() > 1 {
+        println!("{}", container::list_totals(&listed));
+    }
+    exit_code
+}
+
+/// Read the metadata of a .dlz2 file from its header and end frame only, the
+/// standard input is read entirely
+fn read_stream_info(file_path: &str) -> Result<StreamInfo, Failure> {
+    if file_path == STDIN_PATH {
+        let mut content = Vec::new();
+        io::stdin()
+            .lock()
+            .read_to_end(&mut content)
+            .map_err(|err| stream_failure(err, "standard input"))?;
+        return stream_info(&content).map_err(|err| Failure::Corrupt(err.into()));
+    }
+
+    let input_path = Path::new(file_path);
+    let read_ends = || -> io::Result<(Vec<u8>, Vec<u8>, u64)> {
+        let mut file = File::open(input_path)?;
+        let len = file.metadata()?.len();
+        let mut head = Vec::new();
+        (&mut file)
+            .take(HEADER_SIZE as u64)
+            .read_to_end(&mut head)?;
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(len.saturating_sub(END_FRAME_SIZE as u64)))?;
+        file.read_to_end(&mut tail)?;
+        Ok((head, tail, len))
+    };
+    let (head, tail, len) = read_ends().map_err(|err| io_failure("can't read", input_path, err))?;
+    StreamInfo::read(&head, &tail, len).map_err(|err| Failure::Corrupt(err.into()))
+}
+
+fn process_file(config: &Config, input_path: &Path) -> Result<(), Failure> {
+    let metadata =
+        fs::metadata(input_path).map_err(|err| io_failure("can't open", input_path, err))?;
+    if !metadata.is_file() {
+        return Err(Failure::Environment(format!(
+            "{} is not a regular file, skipping",
+            input_path.display()
+        )));
+    }
+
+    let output_path = match config.mode {
+        _ if config.stdout => None,
+        Mode::Test => None,
+        Mode::Compress => Some(config.compressed_path(input_path).ok_or_else(|| {
+            Failure::Environment(format!(
+                "{} already has the suffix of the output format, skipping",
+                input_path.display()
+            ))
+        })?),
+        Mode::Decompress => {
+            let (output_path, known_suffix) = cli::decompressed_path(input_path);
+            if !known_suffix && !config.quiet {
+                eprintln!(
+                    "dlzip2: can't guess the original name of {}, using {}",
+                    input_path.display(),
+                    output_path.display()
+                );
+            }
+            Some(output_path)
+        }
+    };
+    if let Some(output_path) = &output_path {
+        if output_path.exists() && !config.force {
+            return Err(Failure::Environment(format!(
+                "output file {} already exists, skipping",
+                output_path.display()
+            )));
+        }
+    }
+
+    let input = File::open(input_path).map_err(|err| io_failure("can't open", input_path, err))?;
+    let input = BufReader::new(input);
+    let failure = |err| stream_failure(err, &input_path.display().to_string());
+    let (input_len, output_len) = match &output_path {
+        None if config.mode == Mode::Test => {
+            (transcode(config, input, io::sink()).map_err(failure)?, 0)
+        }
+        None => {
+            let mut output = CountingWriter {
+                writer: io::stdout().lock(),
+                count: 0,
+            };
+            let input_len = transcode(config, input, &mut output)
+                .and_then(|input_len| output.flush().map(|_| input_len))
+                .map_err(failure)?;
+            (input_len, output.count)
+        }
+        Some(output_path) => {
+            write_output(config, input, output_path, &metadata).map_err(failure)?
+        }
+    };
+
+    if config.verbose {
+        report(config, input_path, input_len, output_len);
+    }
+
+    if output_path.is_some() && !config.keep {
+        fs::remove_file(input_path).map_err(|err| io_failure("can't remove", input_path, err))?;
+    }
+    Ok(())
+}
+
+/// De/compress the standard input to the standard output
+fn process_stdin(config: &Config) -> Result<(), Failure> {
+    let mut output = CountingWriter {
+        writer: io::stdout().lock(),
+        count: 0,
+    };
+    let failure = |err| stream_failure(err, "standard input or output");
+    let input_len = if config.mode == Mode::Test {
+        transcode(config, io::stdin().lock(), io::sink())
+    } else {
+        transcode(config, io::stdin().lock(), &mut output)
+    }
+    .map_err(failure)?;
+    output.flush().map_err(failure)?;
+
+    if config.verbose && config.mode != Mode::Test {
+        report(config, Path::new("(stdin)"), input_len, output.count);
+    }
+    Ok(())
+}
+
+/// De/compress input into output, return the number of bytes read when
+/// compressing. .dlz2 content is streamed a block at a time, so memory use
+/// doesn't depend on its size, bzip2 content is held whole
+fn transcode(config: &Config, mut input: impl Read, mut output: impl Write) -> io::Result<u64> {
+    match config.mode {
+        Mode::Compress if config.bz2 => {
+            let mut content = Vec::new();
+            input.read_to_end(&mut content)?;
+            output.write_all(&bzip2::compress(&content, config.level)?)?;
+            Ok(content.len() as u64)
+        }
+        Mode::Compress => {
+            let mut encoder = Dlzip2Encoder::with_options(output, config.options())?;
+            let input_len = io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+            Ok(input_len)
+        }
+        Mode::Decompress | Mode::Test => {
+            // The signature tells the format
+            let mut signature = Vec::new();
+            input.by_ref().take(4).read_to_end(&mut signature)?;
+            let mut input = signature.as_slice().chain(input);
+
+            if bzip2::is_bzip2(&signature) {
+                let mut content = Vec::new();
+                input.read_to_end(&mut content)?;
+                output.write_all(&decompress(&content)?)?;
+            } else {
+                let mut decoder = Dlzip2Decoder::new(input)?;
+                io::copy(&mut decoder, &mut output)?;
+            }
+            Ok(0)
+        }
+    }
+}
+
+/// Write the de/compressed input into the output file, with the permissions
+/// and modification time of the input. It is removed if writing fails, return
+/// the number of bytes read when compressing and written
+fn write_output(
+    config: &Config,
+    input: impl Read,
+    output_path: &Path,
+    metadata: &Metadata,
+) -> io::Result<(u64, u64)> {
+    let mut output = CountingWriter {
+        writer: BufWriter::new(File::create(output_path)?),
+        count: 0,
+    };
+    let written = transcode(config, input, &mut output).and_then(|input_len| {
+        let file = output.writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        Ok((input_len, output.count, file))
+    });
+    let (input_len, output_len, file) = match written {
+        Ok(written) => written,
+        Err(err) => {
+            let _ = fs::remove_file(output_path);
+            return Err(err);
+        }
+    };
+
+    // Like bzip2, failing to copy them isn't an error
+    let _ = file.set_permissions(metadata.permissions());
+    if let Ok(modified) = metadata.modified() {
+        let _ = file.set_modified(modified);
+    }
+    Ok((input_len, output_len))
+}
+
+fn report(config: &Config, input_path: &Path, input_len: u64, output_len: u64) {
+    match config.mode {
+        Mode::Compress => {
+            let ratio = input_len as f64 / output_len as f64;
+            let saved = if input_len == 0 {
+                0.0
+            } else {
+                100.0 * (1.0 - output_len as f64 / input_len as f64)
+            };
+            eprintln!(
+                "  {}: {:6.3}:1, {:6.3} bits/byte, {:5.2}% saved, {} in, {} out.",
+                input_path.display(),
+                ratio,
+                8.0 / ratio,
+                saved,
+                input_len,
+                output_len
+            );
+        }
+        Mode::Decompress | Mode::Test => eprintln!("  {}: done", input_path.display()),
+    }
+}
@@ -1,134 +1,289 @@
-use crate::bwt::BWT_MARKER;
+const ONES: u128 = u128::MAX / 0xff;
+const HIGH_BITS: u128 = ONES << 7;
 
-struct List<T> {
-    head: Link<T>,
+/// List of the bytes by most recent use. Most ranks are small on the output of
+/// the BWT, so the first 16 bytes are kept in a u128 (rank 0 in the low bits)
+/// and moved without branching on the rank, the others stay in an array
+struct MtfList {
+    head: u128,
+    /// Bytes from rank 16, the first 16 entries are unused
+    list: [u8; 256],
 }
 
-type Link<T> = Option<Box<Node<T>>>;
-
-struct Node<T> {
-    element: T,
-    next: Link<T>,
-}
-
-impl<T> List<T> {
+impl MtfList {
     fn new() -> Self {
-        List { head: None }
+        let mut list = [0u8; 256];
+        for (idx, el) in list.iter_mut().enumerate() {
+            *el = idx as u8;
+        }
+        let head = u128::from_le_bytes(list[..16].try_into().unwrap());
+        MtfList { head, list }
     }
 
-    fn push(&mut self, elem: T) {
-        let new_node = Node {
-            element: elem,
-            next: self.head.take(),
-        };
-        self.head = Some(Box::new(new_node));
+    fn rank(&self, symbol: u8) -> usize {
+        // The lowest null byte of head ^ symbol is the rank
+        let diff = self.head ^ (symbol as u128 * ONES);
+        let null_bytes = diff.wrapping_sub(ONES) & !diff & HIGH_BITS;
+        if null_bytes != 0 {
+            null_bytes.trailing_zeros() as usize / 8
+        } else {
+            find_rank(&self.list, symbol)
+        }
     }
 
-    #[allow(dead_code)]
-    fn pop(&mut self) -> Option<T> {
-        self.head.take().map(|node| {
-            self.head = node.next;
-            node.element
-        })
+    /// Move the byte at rank to the front and return it
+    fn move_to_front(&mut self, rank: usize) -> u8 {
+        let symbol;
+        if rank < 16 {
+            symbol = (self.head >> (8 * rank)) as u8;
+            let moved = u128::MAX >> (8 * (15 - rank));
+            self.head = (self.head << 8 | symbol as u128) & moved | self.head & !moved;
+        } else {
+            symbol = self.list[rank];
+            self.list.copy_within(16..rank, 17);
+            self.list[16] = (self.head >> 120) as u8;
+            self.head = self.head << 8 | symbol as u128;
+        }
+        symbol
     }
+}
+
+/// Rank of symbol in list from rank 16, where it must be. On x86_64 the list
+/// is searched 16 bytes at a time with SSE2, which is part of its baseline
+#[cfg(target_arch = "x86_64")]
+fn find_rank(list: &[u8; 256], symbol: u8) -> usize {
+    use std::arch::x86_64::{
+        __m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8,
+    };
 
-    #[allow(dead_code)]
-    fn peek(&self) -> Option<&T> {
-        self.head.as_ref().map(|node| &node.element)
+    // SAFETY: SSE2 is always available on x86_64, and each load reads 16
+    // bytes inside list
+    unsafe {
+        let needle = _mm_set1_epi8(symbol as i8);
+        for chunk in (16..256).step_by(16) {
+            let bytes = _mm_loadu_si128(list.as_ptr().add(chunk) as *const __m128i);
+            let mask = _mm_movemask_epi8(_mm_cmpeq_epi8(bytes, needle));
+            if mask != 0 {
+                return chunk + mask.trailing_zeros() as usize;
+            }
+        }
     }
+    unreachable!("the mtf list holds every byte")
 }
 
-impl List<usize> {
-    fn find_npi(&mut self, symbol: usize) -> usize {
-        let mut idx = 0;
-        let mut current_node = self.head.as_mut().unwrap().as_mut();
+#[cfg(not(target_arch = "x86_64"))]
+fn find_rank(list: &[u8; 256], symbol: u8) -> usize {
+    16 + list[16..]
+        .iter()
+        .position(|&el| el == symbol)
+        .expect("the mtf list holds every byte")
+}
 
-        if current_node.element == symbol {
-            return idx;
-        }
+/// Move-to-front coding of the bytes of content, each byte is replaced by
+/// its rank in the list of recently used bytes
+pub fn mtf_encode(text_content: &[u8]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(text_content.len());
+    let mut list = MtfList::new();
 
-        idx += 1;
+    for &symbol in text_content.iter() {
+        let rank = list.rank(symbol);
+        list.move_to_front(rank);
+        result.push(rank as u8);
+    }
 
-        while current_node.next.as_ref().unwrap().element != symbol {
-            current_node = current_node.next.as_mut().unwrap();
-            idx += 1
-        }
+    result
+}
 
-        let mut next = current_node.next.take().unwrap();
-        current_node.next = next.next;
-        next.next = self.head.take();
-        self.head = Some(next);
+/// Inverse of mtf_encode
+pub fn mtf_decode(text_content: &[u8]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(text_content.len());
+    let mut list = MtfList::new();
 
-        idx
+    for &rank in text_content.iter() {
+        result.push(list.move_to_front(rank as usize));
     }
 
-    fn find_np(&mut self, idx: usize) -> usize {
-        let mut current_node = self.head.as_mut().unwrap().as_mut();
+    result
+}
 
-        if idx == 0 {
-            return current_node.element;
-        }
+#[cfg(test)]
+mod test {
+    use super::{mtf_decode, mtf_encode, MtfList};
+    use crate::bwt::{bwt_encode_block, MAX_VEC_SIZE};
+    use std::time::Instant;
 
-        let mut c = 1;
-        while c < idx {
-            current_node = current_node.next.as_mut().unwrap();
-            c += 1;
-        }
+    // The linked list implementation mtf_encode and mtf_decode replaced,
+    // kept to check they give the same output and to compare their speed
+    struct List<T> {
+        head: Link<T>,
+    }
 
-        let mut next = current_node.next.take().unwrap();
-        current_node.next = next.next;
-        next.next = self.head.take();
-        self.head = Some(next);
+    type Link<T> = Option<Box<Node<T>>>;
 
-        self.head.as_ref().unwrap().element
+    struct Node<T> {
+        element: T,
+        next: Link<T>,
     }
-}
 
-pub fn mtf_encode(text_content: Vec<usize>) -> Vec<usize> {
-    let mut result: Vec<usize> = Vec::with_capacity(text_content.len());
-    let mut list = List::new();
+    impl List<u8> {
+        fn new() -> Self {
+            let mut list = List { head: None };
+            for symbol in (0..=u8::MAX).rev() {
+                list.head = Some(Box::new(Node {
+                    element: symbol,
+                    next: list.head.take(),
+                }));
+            }
+            list
+        }
 
-    for symbol in (0..BWT_MARKER + 1).rev() {
-        list.push(symbol)
-    }
+        fn find_npi(&mut self, symbol: u8) -> u8 {
+            let mut idx = 0;
+            let mut current_node = self.head.as_mut().unwrap().as_mut();
 
-    for &symbol in text_content.iter() {
-        let idx = list.find_npi(symbol);
-        result.push(idx);
-    }
+            if current_node.element == symbol {
+                return idx;
+            }
 
-    result
-}
+            idx += 1;
 
-pub fn mtf_decode(text_content: Vec<usize>) -> Vec<usize> {
-    let mut result: Vec<usize> = Vec::with_capacity(text_content.len());
-    let mut list = List::new();
+            while current_node.next.as_ref().unwrap().element != symbol {
+                current_node = current_node.next.as_mut().unwrap();
+                idx += 1
+            }
 
-    for symbol in (0..BWT_MARKER + 1).rev() {
-        list.push(symbol)
+            let mut next = current_node.next.take().unwrap();
+            current_node.next = next.next;
+            next.next = self.head.take();
+            self.head = Some(next);
+
+            idx
+        }
+
+        fn find_np(&mut self, idx: u8) -> u8 {
+            let mut current_node = self.head.as_mut().unwrap().as_mut();
+
+            if idx == 0 {
+                return current_node.element;
+            }
+
+            let mut c = 1;
+            while c < idx {
+                current_node = current_node.next.as_mut().unwrap();
+                c += 1;
+            }
+
+            let mut next = current_node.next.take().unwrap();
+            current_node.next = next.next;
+            next.next = self.head.take();
+            self.head = Some(next);
+
+            self.head.as_ref().unwrap().element
+        }
     }
 
-    for &symbol in text_content.iter() {
-        let idx = list.find_np(symbol);
-        result.push(idx);
+    fn linked_list_encode(text_content: &[u8]) -> Vec<u8> {
+        let mut list = List::new();
+        text_content.iter().map(|&el| list.find_npi(el)).collect()
     }
 
-    result
-}
+    fn linked_list_decode(text_content: &[u8]) -> Vec<u8> {
+        let mut list = List::new();
+        text_content.iter().map(|&el| list.find_np(el)).collect()
+    }
 
-#[cfg(test)]
-mod test {
-    use super::{mtf_encode, mtf_decode, BWT_MARKER};
+    /// Last columns of the BWT of a text, what mtf_encode gets in the pipeline
+    fn bwt_text(len: usize) -> Vec<u8> {
+        crate::test_common::bench_text(len)
+            .chunks(MAX_VEC_SIZE)
+            .flat_map(|block| bwt_encode_block(block).0)
+            .collect()
+    }
 
     #[test]
     pub fn test_mtf() {
-        let mut text_content = vec![15, 15, 15, 15, 16, 16, 231, 231, 192, 255];
-        text_content.push(BWT_MARKER);
+        let text_content = vec![15, 15, 15, 15, 16, 16, 231, 231, 192, 255, 0];
         println!("{:?}", text_content);
-        let _code = mtf_encode(text_content.clone());
+        let _code = mtf_encode(&text_content);
         println!("{:?}", _code);
-        let _decode = mtf_decode(_code);
+        assert_eq!(_code, vec![15, 0, 0, 0, 16, 0, 231, 0, 193, 255, 5]);
+        let _decode = mtf_decode(&_code);
         println!("{:?}", _decode);
         assert_eq!(text_content, _decode);
     }
+
+    #[test]
+    fn test_mtf_list() {
+        let mut list = MtfList::new();
+        for symbol in 0..=u8::MAX {
+            assert_eq!(list.rank(symbol), symbol as usize);
+        }
+        for symbol in 0..=u8::MAX {
+            assert_eq!(list.move_to_front(list.rank(symbol)), symbol);
+        }
+        for symbol in 0..=u8::MAX {
+            assert_eq!(list.rank(symbol), 255 - symbol as usize);
+        }
+    }
+
+    #[test]
+    fn test_mtf_linked_list() {
+        let text_content = bwt_text(200_000);
+        let code = linked_list_encode(&text_content);
+        assert_eq!(mtf_encode(&text_content), code);
+        assert_eq!(mtf_decode(&code), text_content);
+        assert_eq!(linked_list_decode(&code), text_content);
+
+        // Every byte, in an order giving every rank
+        let text_content: Vec<u8> = (0..=u8::MAX).rev().chain(0..=u8::MAX).collect();
+        let code = linked_list_encode(&text_content);
+        assert_eq!(mtf_encode(&text_content), code);
+        assert_eq!(mtf_decode(&code), text_content);
+    }
+
+    #[ignore = "benchmark, run with cargo test --release bench_mtf -- --ignored --nocapture"]
+    #[test]
+    fn bench_mtf() {
+        // Small ranks on the BWT of a text, uniform ones on random bytes
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let random_bytes: Vec<u8> = (0..5_000_000)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 56) as u8
+            })
+            .collect();
+
+        for (content_name, text_content) in [
+            ("bwt of a text", bwt_text(20_000_000)),
+            ("random bytes", random_bytes),
+        ] {
+            let code = mtf_encode(&text_content);
+            let mega_bytes = code.len() as f64 / 1e6;
+
+            for (name, encode, decode) in [
+                (
+                    "linked list",
+                    linked_list_encode as fn(&[u8]) -> Vec<u8>,
+                    linked_list_decode as fn(&[u8]) -> Vec<u8>,
+                ),
+                ("array", mtf_encode, mtf_decode),
+            ] {
+                let start = Instant::now();
+                assert_eq!(encode(&text_content), code);
+                let encode_time = start.elapsed();
+
+                let start = Instant::now();
+                assert_eq!(decode(&code), text_content);
+                let decode_time = start.elapsed();
+
+                println!(
+                    "{content_name}, {name}: encode {:.1} MB/s, decode {:.1} MB/s",
+                    mega_bytes / encode_time.as_secs_f64(),
+                    mega_bytes / decode_time.as_secs_f64()
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,321 @@
+// The #[pymethods] expansion of pyo3 0.20 trips this lint of recent compilers
+#![allow(non_local_definitions)]
+
+use std::io::Write;
+
+use pyo3::buffer::PyBuffer;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyBufferError, PyEOFError, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use crate::container::{self, flag_names, StreamInfo, FLAG_NAMES};
+use crate::error::{DlzipError, OptionsError, VerifyError};
+use crate::{
+    bzip2, compress_with, decompress, huffman, stream_info, verify, Dlzip2Encoder, Options,
+    PushDecoder,
+};
+
+// Subclass of ValueError, raised when content can't be decompressed
+create_exception!(dlzip2, Dlzip2Error, PyValueError);
+create_exception!(dlzip2, CorruptDataError, Dlzip2Error);
+create_exception!(dlzip2, TruncatedInputError, Dlzip2Error);
+create_exception!(dlzip2, ChecksumError, Dlzip2Error);
+
+impl From<DlzipError> for PyErr {
+    fn from(err: DlzipError) -> PyErr {
+        let message = err.to_string();
+        dlzip2_error(&err, message)
+    }
+}
+
+/// Options out of their range raise a ValueError
+impl From<OptionsError> for PyErr {
+    fn from(err: OptionsError) -> PyErr {
+        PyValueError::new_err(err.to_string())
+    }
+}
+
+/// The exception raised for err, with the given message
+fn dlzip2_error(err: &DlzipError, message: String) -> PyErr {
+    match err {
+        DlzipError::Truncated => TruncatedInputError::new_err(message),
+        DlzipError::ChecksumMismatch { .. } => ChecksumError::new_err(message),
+        DlzipError::CorruptHeader
+        | DlzipError::CorruptBlockHeader
+        | DlzipError::CorruptHuffmanTable
+        | DlzipError::CorruptData
+        | DlzipError::MissingBwtMarker
+        | DlzipError::LengthMismatch { .. }
+        | DlzipError::BlockCountMismatch { .. } => CorruptDataError::new_err(message),
+        // Not a stream this version can read
+        DlzipError::BadMagic
+        | DlzipError::UnsupportedVersion(_)
+        | DlzipError::UnsupportedFlags(_)
+        | DlzipError::LegacyLayout
+        | DlzipError::RandomisedBlock => Dlzip2Error::new_err(message),
+    }
+}
+
+/// Borrow the content of an object supporting the buffer protocol (bytes,
+/// bytearray, memoryview, mmap, numpy arrays of uint8...) without copying it
+fn as_bytes(buffer: &PyBuffer<u8>) -> PyResult<&[u8]> {
+    if !buffer.is_c_contiguous() {
+        return Err(PyBufferError::new_err("buffer is not contiguous"));
+    }
+    if buffer.len_bytes() == 0 {
+        return Ok(&[]);
+    }
+    // SAFETY: the buffer is contiguous, and its exporter can't resize or free
+    // it while it is held by the PyBuffer. As with the bz2 module, the content
+    // of a mutable buffer must not be modified by another thread during a call
+    Ok(unsafe { std::slice::from_raw_parts(buffer.buf_ptr() as *const u8, buffer.len_bytes()) })
+}
+
+/// The de/compression functions and methods release the GIL while the content
+/// goes through the pipeline, so python threads can de/compress in parallel
+#[pyfunction]
+#[pyo3(signature = (text_content, multi_table=false, max_code_len=huffman::DEFAULT_MAX_CODE_LEN, threads=1))]
+fn _compress<'py>(
+    py: Python<'py>,
+    text_content: PyBuffer<u8>,
+    multi_table: bool,
+    max_code_len: u8,
+    threads: usize,
+) -> PyResult<&'py PyBytes> {
+    let options = Options {
+        multi_table,
+        max_code_len,
+        threads,
+        ..Options::default()
+    };
+    let content = as_bytes(&text_content)?;
+    let output = py.allow_threads(|| compress_with(content, &options))?;
+    Ok(PyBytes::new(py, &output))
+}
+
+#[pyfunction]
+#[pyo3(signature = (text_content, level=bzip2::DEFAULT_LEVEL))]
+fn _compress_bz2<'py>(
+    py: Python<'py>,
+    text_content: PyBuffer<u8>,
+    level: u8,
+) -> PyResult<&'py PyBytes> {
+    let content = as_bytes(&text_content)?;
+    let output = py.allow_threads(|| bzip2::compress(content, level))?;
+    Ok(PyBytes::new(py, &output))
+}
+
+#[pyfunction]
+fn _decompress(py: Python<'_>, text_content: PyBuffer<u8>) -> PyResult<&PyBytes> {
+    let content = as_bytes(&text_content)?;
+    let output = py.allow_threads(|| decompress(content))?;
+    Ok(PyBytes::new(py, &output))
+}
+
+/// Decode content without keeping it and return its decompressed length. The
+/// exception raised has the index of the failing block in its block attribute,
+/// None when the error isn't in a block
+#[pyfunction]
+fn _verify(py: Python<'_>, text_content: PyBuffer<u8>) -> PyResult<u64> {
+    let content = as_bytes(&text_content)?;
+    py.allow_threads(|| verify(content))
+        .or_else(|err: VerifyError| {
+            let py_err = dlzip2_error(&err.error, err.to_string());
+            py_err.value(py).setattr("block", err.block)?;
+            Err(py_err)
+        })
+}
+
+/// Metadata recorded in the header and end frame of a .dlz2 stream, as a dict
+#[pyfunction]
+fn _stream_info<'py>(py: Python<'py>, text_content: PyBuffer<u8>) -> PyResult<&'py PyDict> {
+    let info = stream_info(as_bytes(&text_content)?)?;
+    let dict = PyDict::new(py);
+    dict.set_item("compressed_len", info.compressed_len)?;
+    dict.set_item("original_len", info.original_len)?;
+    dict.set_item("block_count", info.block_count)?;
+    dict.set_item("block_size", info.block_size)?;
+    dict.set_item("version", info.version)?;
+    dict.set_item("flags", flag_names(info.flags))?;
+    dict.set_item("stream_crc", info.stream_crc)?;
+    Ok(dict)
+}
+
+/// StreamInfo of a dict returned by _stream_info
+fn info_from_dict(dict: &PyDict) -> PyResult<StreamInfo> {
+    let item = |key| dict.get_item(key)?.ok_or_else(|| PyKeyError::new_err(key));
+    let names: Vec<String> = item("flags")?.extract()?;
+    let flags = FLAG_NAMES
+        .into_iter()
+        .filter(|(_, name)| names.iter().any(|flag_name| flag_name == name))
+        .fold(0, |flags, (flag, _)| flags | flag);
+    Ok(StreamInfo {
+        version: item("version")?.extract()?,
+        flags,
+        block_size: item("block_size")?.extract()?,
+        block_count: item("block_count")?.extract()?,
+        compressed_len: item("compressed_len")?.extract()?,
+        original_len: item("original_len")?.extract()?,
+        stream_crc: item("stream_crc")?.extract()?,
+    })
+}
+
+/// Line of the --list table describing a stream_info dict, the native CLI
+/// prints the same
+#[pyfunction]
+fn _list_line(info: &PyDict, file_name: &str) -> PyResult<String> {
+    Ok(container::list_line(&info_from_dict(info)?, file_name))
+}
+
+/// Last line of the --list table, summing the stream_info dicts listed
+#[pyfunction]
+fn _list_totals(infos: Vec<&PyDict>) -> PyResult<String> {
+    let infos = infos
+        .into_iter()
+        .map(info_from_dict)
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(container::list_totals(&infos))
+}
+
+/// Compress data incrementally, as bz2.BZ2Compressor does. With several
+/// threads, that many full blocks are buffered and compressed at once
+#[pyclass(module = "dlzip2")]
+struct Dlzip2Compressor {
+    /// None once flushed
+    encoder: Option<Dlzip2Encoder<Vec<u8>>>,
+}
+
+#[pymethods]
+impl Dlzip2Compressor {
+    #[new]
+    #[pyo3(signature = (multi_table=false, max_code_len=huffman::DEFAULT_MAX_CODE_LEN, threads=1))]
+    fn new(multi_table: bool, max_code_len: u8, threads: usize) -> PyResult<Self> {
+        let options = Options {
+            multi_table,
+            max_code_len,
+            threads,
+            ..Options::default()
+        };
+        options.check()?;
+        Ok(Dlzip2Compressor {
+            encoder: Some(Dlzip2Encoder::with_options(Vec::new(), options)?),
+        })
+    }
+
+    /// Compress data, return the part of the stream completed so far
+    /// (possibly empty, blocks are only written once full)
+    fn compress<'py>(&mut self, py: Python<'py>, data: PyBuffer<u8>) -> PyResult<&'py PyBytes> {
+        let data = as_bytes(&data)?;
+        let encoder = self
+            .encoder
+            .as_mut()
+            .ok_or_else(|| PyValueError::new_err("Compressor has been flushed"))?;
+        py.allow_threads(|| encoder.write_all(data))?;
+        let output = std::mem::take(encoder.get_mut());
+        Ok(PyBytes::new(py, &output))
+    }
+
+    /// Finish the stream and return the rest of it, the compressor can't be
+    /// used afterward
+    fn flush<'py>(&mut self, py: Python<'py>) -> PyResult<&'py PyBytes> {
+        let encoder = self
+            .encoder
+            .take()
+            .ok_or_else(|| PyValueError::new_err("Repeated call to flush()"))?;
+        let output = py.allow_threads(|| encoder.finish())?;
+        Ok(PyBytes::new(py, &output))
+    }
+}
+
+/// Decompress a .dlz2 stream incrementally, as bz2.BZ2Decompressor does
+#[pyclass(module = "dlzip2")]
+struct Dlzip2Decompressor {
+    decoder: PushDecoder,
+    /// Decompressed content not returned yet because of max_length
+    pending: Vec<u8>,
+    needs_input: bool,
+}
+
+#[pymethods]
+impl Dlzip2Decompressor {
+    #[new]
+    fn new() -> Self {
+        Dlzip2Decompressor {
+            decoder: PushDecoder::new(),
+            pending: Vec::new(),
+            needs_input: true,
+        }
+    }
+
+    /// Decompress data, return at most max_length bytes when it isn't
+    /// negative. Content is decompressed block by block, so nothing is
+    /// returned until a whole block has been given
+    #[pyo3(signature = (data, max_length=-1))]
+    fn decompress<'py>(
+        &mut self,
+        py: Python<'py>,
+        data: PyBuffer<u8>,
+        max_length: isize,
+    ) -> PyResult<&'py PyBytes> {
+        if self.eof() {
+            return Err(PyEOFError::new_err("End of stream already reached"));
+        }
+        self.decoder.feed(as_bytes(&data)?);
+
+        let max_length = usize::try_from(max_length).unwrap_or(usize::MAX);
+        let (decoder, pending) = (&mut self.decoder, &mut self.pending);
+        let input_exhausted = py.allow_threads(|| {
+            while pending.len() < max_length {
+                match decoder.next_block()? {
+                    Some(mut block) => pending.append(&mut block),
+                    None => return Ok(!decoder.eof()),
+                }
+            }
+            Ok::<_, DlzipError>(false)
+        })?;
+
+        let output_len = self.pending.len().min(max_length);
+        let output = PyBytes::new(py, &self.pending[..output_len]);
+        self.pending.drain(..output_len);
+        self.needs_input = input_exhausted && self.pending.is_empty();
+        Ok(output)
+    }
+
+    /// True once the end of the stream has been reached and all the content returned
+    #[getter]
+    fn eof(&self) -> bool {
+        self.decoder.eof() && self.pending.is_empty()
+    }
+
+    /// Data found after the end of the stream
+    #[getter]
+    fn unused_data<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, self.decoder.unused_data())
+    }
+
+    /// False if decompress can return more content without new input
+    #[getter]
+    fn needs_input(&self) -> bool {
+        self.needs_input
+    }
+}
+
+#[pymodule]
+fn _dlzip2(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add("Dlzip2Error", py.get_type::<Dlzip2Error>())?;
+    m.add("CorruptDataError", py.get_type::<CorruptDataError>())?;
+    m.add("TruncatedInputError", py.get_type::<TruncatedInputError>())?;
+    m.add("ChecksumError", py.get_type::<ChecksumError>())?;
+    m.add_class::<Dlzip2Compressor>()?;
+    m.add_class::<Dlzip2Decompressor>()?;
+    m.add_function(wrap_pyfunction!(_compress, m)?)?;
+    m.add_function(wrap_pyfunction!(_compress_bz2, m)?)?;
+    m.add_function(wrap_pyfunction!(_decompress, m)?)?;
+    m.add_function(wrap_pyfunction!(_verify, m)?)?;
+    m.add_function(wrap_pyfunction!(_stream_info, m)?)?;
+    m.add_function(wrap_pyfunction!(_list_line, m)?)?;
+    m.add_function(wrap_pyfunction!(_list_totals, m)?)?;
+    m.add("_LIST_HEADER", container::LIST_HEADER)?;
+    Ok(())
+}
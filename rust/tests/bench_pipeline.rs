@@ -0,0 +1,74 @@
+//! Throughput and peak memory of the pipeline on 100 MB of text, run with
+//! `cargo test --release --test bench_pipeline -- --ignored --nocapture`
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use dlzip2::{compress, compress_with, decompress, Options};
+
+mod common;
+use common::bench_text;
+
+/// Track the heap allocated by the benchmark, to measure its peak memory
+struct PeakAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+            PEAK.fetch_max(allocated + layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+/// Run f, print its throughput on len bytes and the peak of memory it allocated
+fn measure<T>(name: &str, len: usize, f: impl FnOnce() -> T) -> T {
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(allocated, Ordering::Relaxed);
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    println!(
+        "{}: {:.2} MB/s, peak memory {} MB",
+        name,
+        len as f64 / 1e6 / elapsed.as_secs_f64(),
+        (PEAK.load(Ordering::Relaxed) - allocated) >> 20
+    );
+    result
+}
+
+#[ignore = "benchmark, run with cargo test --release --test bench_pipeline -- --ignored --nocapture"]
+#[test]
+fn bench_pipeline() {
+    let text = bench_text(100 << 20);
+
+    let code = measure("compress 100 MB", text.len(), || compress(&text));
+    let decode = measure("decompress 100 MB", text.len(), || {
+        decompress(&code).unwrap()
+    });
+    assert!(decode == text);
+
+    let options = Options {
+        threads: 0,
+        ..Options::default()
+    };
+    let name = format!("compress 100 MB on {} threads", options.thread_count());
+    let parallel_code = measure(&name, text.len(), || {
+        compress_with(&text, &options).unwrap()
+    });
+    assert!(parallel_code == code);
+}
@@ -0,0 +1,26 @@
+//! Helpers shared by the test targets, the unit tests of the library include
+//! them too
+
+/// Words drawn with a skewed distribution, compressing like a text
+pub fn bench_text(len: usize) -> Vec<u8> {
+    let words: Vec<&str> = "the of and to in is was that for it with as his on be at by \
+        had not are but from or have an they which one you were her all she there would \
+        their we him been has when who will more no if out so said what up its about into \
+        than them can only other new some could time these two may then do first any my \
+        now such like our over man me even most made after also did many before must \
+        through back years where much your way well down should because each just those"
+        .split_whitespace()
+        .collect();
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut text = Vec::with_capacity(len + 16);
+    while text.len() < len {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        let rank = (state >> 33) as usize % words.len() * ((state >> 20) as usize % 4 + 1) / 4;
+        text.extend_from_slice(words[rank].as_bytes());
+        text.push(if state >> 61 == 0 { b'\n' } else { b' ' });
+    }
+    text.truncate(len);
+    text
+}
@@ -1,13 +1,15 @@
-const ZRLE_ONE: usize = 257;
-const ZRLE_TWO: usize = 258;
+use crate::error::DlzipError;
 
-fn from_int_to_zrle(length: usize) -> Vec<usize> {
-    let mut result: Vec<usize> = Vec::new();
+const ZRLE_ONE: u16 = 257;
+const ZRLE_TWO: u16 = 258;
+
+fn from_int_to_zrle(length: usize) -> Vec<u16> {
+    let mut result: Vec<u16> = Vec::new();
     let mut q_n = length;
     let mut q_np1;
 
     while q_n > 0 {
-        q_np1 = (q_n + 2 - 1) / 2 - 1;
+        q_np1 = q_n.div_ceil(2) - 1;
         if q_n - 2 * q_np1 == 1 {
             result.push(ZRLE_ONE);
         } else if q_n - 2 * q_np1 == 2{
@@ -20,28 +22,32 @@ fn from_int_to_zrle(length: usize) -> Vec<usize> {
     result
 }
 
-fn from_zrle_to_int(zrle: &[usize]) -> usize {
+/// Length of the run coded by zrle, None if it doesn't fit in a usize
+fn from_zrle_to_int(zrle: &[u16]) -> Option<usize> {
     let mut result: usize = 0;
 
     for (k, &el) in zrle.iter().rev().enumerate() {
+        let power = 2usize.checked_pow(k as u32)?;
         if el == ZRLE_ONE {
-            result += 2usize.pow(k as u32);
+            result = result.checked_add(power)?;
         } else if el == ZRLE_TWO {
-            result += 2 * 2usize.pow(k as u32);
+            result = result.checked_add(power.checked_mul(2)?)?;
         }
     }
 
-    result
+    Some(result)
 }
 
-pub fn zrle_encode(text_content: Vec<usize>) -> Vec<usize> {
-    let mut result: Vec<usize> = Vec::new();
+/// Replace each run of zeros of the MTF output by its length, written in
+/// bijective base 2 with ZRLE_ONE and ZRLE_TWO
+pub fn zrle_encode(text_content: &[u8]) -> Vec<u16> {
+    let mut result: Vec<u16> = Vec::with_capacity(text_content.len());
 
     let mut idx_deb = 0;
     let mut idx_end;
     while idx_deb < text_content.len() {
         if text_content[idx_deb] != 0 {
-            result.push(text_content[idx_deb]);
+            result.push(text_content[idx_deb] as u16);
             idx_deb += 1;
         } else if text_content[idx_deb] == 0 {
             idx_end = idx_deb + 1;
@@ -56,35 +62,45 @@ pub fn zrle_encode(text_content: Vec<usize>) -> Vec<usize> {
     result
 }
 
-pub fn zrle_decode(text_content: Vec<usize>) -> Vec<usize> {
-    let mut result: Vec<usize> = Vec::with_capacity(text_content.len());
+/// Inverse of zrle_encode, fails if the decoded content would be longer than
+/// max_len or if a symbol is neither a byte nor a run symbol
+pub fn zrle_decode(text_content: &[u16], max_len: usize) -> Result<Vec<u8>, DlzipError> {
+    let mut result: Vec<u8> = Vec::with_capacity(text_content.len().min(max_len));
 
     let mut idx_deb = 0;
     let mut idx_end;
 
     while idx_deb < text_content.len() {
         if (text_content[idx_deb] != ZRLE_ONE) & (text_content[idx_deb] != ZRLE_TWO) {
-            result.push(text_content[idx_deb]);
+            let byte = u8::try_from(text_content[idx_deb]).map_err(|_| DlzipError::CorruptData)?;
+            result.push(byte);
             idx_deb += 1;
         } else {
             idx_end = idx_deb + 1;
             while idx_end < text_content.len() && (text_content[idx_end] == ZRLE_ONE || text_content[idx_end] == ZRLE_TWO) {
                 idx_end += 1;
             }
-            let length = from_zrle_to_int(&text_content[idx_deb..idx_end]);
-            let mut zero_vec = vec![0; length];
-            result.append(&mut zero_vec); 
+            let length =
+                from_zrle_to_int(&text_content[idx_deb..idx_end]).ok_or(DlzipError::CorruptData)?;
+            if length > max_len - result.len() {
+                return Err(DlzipError::CorruptData);
+            }
+            result.resize(result.len() + length, 0);
             idx_deb = idx_end;
         }
+        if result.len() > max_len {
+            return Err(DlzipError::CorruptData);
+        }
     }
 
-    result
+    Ok(result)
 }
 
 
 
 #[cfg(test)]
 mod test {
+    use crate::error::DlzipError;
     use crate::z_rle::{ZRLE_ONE, ZRLE_TWO};
 
     use super::{from_int_to_zrle, from_zrle_to_int, zrle_decode, zrle_encode};
@@ -100,29 +116,38 @@ mod test {
     fn test_deconversion() {
         let zrle = vec![ZRLE_ONE, ZRLE_TWO, ZRLE_TWO];
         let res = from_zrle_to_int(&zrle);
-        assert_eq!(res, 10);
+        assert_eq!(res, Some(10));
+        assert_eq!(from_zrle_to_int(&[ZRLE_TWO; 64]), None);
     }
 
     #[test]
     fn test_both() {
         let num = 123;
-        assert_eq!(num, from_zrle_to_int(&from_int_to_zrle(num)));
+        assert_eq!(Some(num), from_zrle_to_int(&from_int_to_zrle(num)));
     }
 
     #[test]
     fn test_encode() {
-        let content = vec![97, 0, 0, 0, 98, 0, 0, 99, 0, 2, 13, 256];
-        let code = zrle_encode(content.clone());
+        let content = vec![97, 0, 0, 0, 98, 0, 0, 99, 0, 2, 13, 255];
+        let code = zrle_encode(&content);
         println!("{:?}", code);
-        let decode = zrle_decode(code);
+        let decode = zrle_decode(&code, usize::MAX).unwrap();
         assert_eq!(content, decode);
+        assert_eq!(
+            zrle_decode(&code, content.len() - 1),
+            Err(DlzipError::CorruptData)
+        );
+        assert_eq!(
+            zrle_decode(&[97, 256], usize::MAX),
+            Err(DlzipError::CorruptData)
+        );
     }
-    
+
     #[test]
     fn test_zrle() {
-        let text_content = vec![97, 0, 0, 0, 98, 0, 0, 99, 0, 2, 13, 256];
-        let code = zrle_encode(text_content.clone());
-        let decode = zrle_decode(code);
-        assert_eq!(text_content, decode); 
+        let text_content = vec![97, 0, 0, 0, 98, 0, 0, 99, 0, 2, 13, 255];
+        let code = zrle_encode(&text_content);
+        let decode = zrle_decode(&code, usize::MAX).unwrap();
+        assert_eq!(text_content, decode);
     }
 }
\ No newline at end of file